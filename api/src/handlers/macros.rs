@@ -6,12 +6,12 @@ macro_rules! try_handler {
             Err(e) => {
                 let response = super::ErrorResponse{
                     success: false,
-                    error: e.description().to_string()
+                    error: e.to_string()
                 };
                 if let Ok(res) = serde_json::to_string(&response) {
                     return Ok(Response::with((status::InternalServerError, res)))
                 }
-                return Ok(Response::with((status::InternalServerError, e.description())))
+                return Ok(Response::with((status::InternalServerError, e.to_string())))
             }
         }
     };
@@ -21,54 +21,45 @@ macro_rules! try_handler {
             Err(e) => {
                 let response = super::ErrorResponse{
                     success: false,
-                    error: e.description().to_string()
+                    error: e.to_string()
                 };
                 if let Ok(res) = serde_json::to_string(&response) {
                     return Ok(Response::with(($error, res)))
                 }
-                return Ok(Response::with(($error, e.description())))
+                return Ok(Response::with(($error, e.to_string())))
             }
         }
     };
 }
 
 #[macro_export]
-macro_rules! try_store_id {
-    ($headers:expr) => {
-        match $headers.get_raw("x-store-id") {
-            Some(rows) => try_handler!(
-                try_handler!(
-                    String::from_utf8(rows[0].to_owned())
-                    ).parse::<i32>()
-                ),
+macro_rules! try_auth {
+    ($r:expr) => {
+        match $r.extensions.get::<::utils::jwt::AuthContext>() {
+            Some(ctx) => ctx.clone(),
             None => {
                 let response = super::ErrorResponse {
                     success: false,
-                    error: "No store id found".to_string(),
+                    error: "No authorization context found".to_string(),
                 };
                 let res: String = try_handler!(serde_json::to_string(&response));
-                return Ok(Response::with((status::BadRequest, res)))
+                return Ok(Response::with((status::Forbidden, res)))
             }
-        };
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! try_store_id {
+    ($r:expr) => {
+        try_auth!($r).store_id
     };
 }
 
 #[macro_export]
 macro_rules! try_user_uuid {
-    ($headers:expr) => {
-        match $headers.get_raw("x-user-uuid") {
-            Some(rows) =>
-                    try_handler!(String::from_utf8(rows[0].to_owned()))
-                ,
-            None => {
-                let response = super::ErrorResponse {
-                    success: false,
-                    error: "No user uuid found".to_string(),
-                };
-                let res: String = try_handler!(serde_json::to_string(&response));
-                return Ok(Response::with((status::BadRequest, res)))
-            }
-        };
+    ($r:expr) => {
+        try_auth!($r).uuid
     };
 }
 
@@ -80,7 +71,7 @@ macro_rules! try_validate {
             Err(e) => {
                 let response = super::ErrorResponseWithValidation{
                     success: false,
-                    error: e.description().to_string(),
+                    error: "Validation failed".to_string(),
                     validations:
                         e.inner()
                             .values()
@@ -142,7 +133,7 @@ macro_rules! try_validate {
             Err(e) => {
                 let response = super::ErrorResponseWithValidation{
                     success: false,
-                    error: e.description().to_string(),
+                    error: "Validation failed".to_string(),
                     validations:
                         e.inner()
                             .into_iter()
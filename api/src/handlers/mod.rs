@@ -12,22 +12,68 @@ use redis;
 use iron::{status, Handler, IronResult, Request, Response};
 use rusoto_s3::S3Client;
 use utils::pubsub::Manager;
+use utils::mailer::Mailer;
+use utils::jwk::KeyStore;
 use models::pizza::Pizza;
+use serde_json;
+use std::net::IpAddr;
+use std::str;
+use utils::config::trusted_proxy_header;
 
 pub struct Handlers {
     pub user_create: user::UserCreateHandler,
+    pub user_verify_email: user::UserVerifyEmailHandler,
+    pub user_verify_email_resend: user::UserVerifyEmailResendHandler,
     pub user_login: user::UserLoginHandler,
+    pub user_login_two_factor: user::UserLoginTwoFactorHandler,
+    pub user_refresh: user::UserRefreshHandler,
+    pub user_oidc_login: user::UserOidcLoginHandler,
+    pub user_oidc_callback: user::UserOidcCallbackHandler,
+    pub user_reset_password: user::UserResetPasswordHandler,
+    pub user_reset_password_confirm: user::UserResetPasswordConfirmHandler,
     pub user_info: user::UserInfoHandler,
+    pub user_update: user::UserUpdateHandler,
+    pub user_change_password: user::UserChangePasswordHandler,
+    pub user_two_factor_enroll: user::UserTwoFactorEnrollHandler,
+    pub user_two_factor_confirm: user::UserTwoFactorConfirmHandler,
+    pub user_two_factor_disable: user::UserTwoFactorDisableHandler,
+    pub user_export: user::UserExportHandler,
+    pub user_delete: user::UserDeleteHandler,
+    pub user_logout: user::UserLogoutHandler,
+    pub user_logout_all: user::UserLogoutAllHandler,
+    pub user_sessions: user::UserSessionListHandler,
+    pub user_session_delete: user::UserSessionDeleteHandler,
+    pub user_list: user::GetUserListHandler,
+    pub user_set_role: user::UserSetRoleHandler,
+    pub user_clear_lockout: user::UserClearLockoutHandler,
 
     pub ws_ticket: user::UserGetWsTokenHandler,
+    pub jwks: user::JwksHandler,
 
     pub ingredient_list: ingredient::GetIngredientListHandler,
     pub tag_list: tag::GetTagListHandler,
 
     pub store_list: store::GetStoreListHandler,
+    pub invite_create: store::CreateInviteHandler,
+    pub invite_list: store::GetInviteListHandler,
+    pub invite_revoke: store::RevokeInviteHandler,
+    pub accept_mode_get: store::GetAcceptModeHandler,
+    pub accept_mode_set: store::SetAcceptModeHandler,
+    pub kitchen_get: store::GetKitchenHandler,
+    pub kitchen_set: store::SetKitchenHandler,
 
     pub pizza_create: pizza::CreatePizzaHandler,
     pub pizza_list: pizza::GetPizzaListHandler,
+    pub pizza_history: pizza::GetPizzaHistoryHandler,
+    pub pizza_get: pizza::GetPizzaHandler,
+    pub pizza_queue: pizza::GetPizzaQueueHandler,
+    pub pizza_quote: pizza::QuotePizzaHandler,
+    pub pizza_update: pizza::UpdatePizzaHandler,
+    pub pizza_delete: pizza::DeletePizzaHandler,
+    pub pizza_restore: pizza::RestorePizzaHandler,
+    pub pizza_set_status: pizza::SetPizzaStatusHandler,
+    pub pizza_accept: pizza::AcceptPizzaHandler,
+    pub pizza_reject: pizza::RejectPizzaHandler,
 
     pub index_handler: IndexHandler,
 }
@@ -38,21 +84,101 @@ impl Handlers {
         rds: Arc<Mutex<redis::Connection>>,
         s3_client: Arc<Mutex<S3Client>>,
         ps_manager: Arc<Mutex<Manager>>,
+        mailer: Arc<Mutex<Box<dyn Mailer>>>,
+        keys: Arc<KeyStore>,
     ) -> Handlers {
         let database = Arc::new(Mutex::new(db));
-        Pizza::emulate_accept(database.clone(), rds.clone(), ps_manager.clone());
+        Pizza::run_accept_scheduler(database.clone(), rds.clone(), ps_manager.clone());
         Handlers {
-            user_create: user::UserCreateHandler::new(database.clone()),
-            user_login: user::UserLoginHandler::new(database.clone(), rds.clone()),
+            user_create: user::UserCreateHandler::new(
+                database.clone(),
+                rds.clone(),
+                mailer.clone(),
+            ),
+            user_verify_email: user::UserVerifyEmailHandler::new(database.clone(), rds.clone()),
+            user_verify_email_resend: user::UserVerifyEmailResendHandler::new(
+                database.clone(),
+                rds.clone(),
+                mailer.clone(),
+            ),
+            user_login: user::UserLoginHandler::new(database.clone(), rds.clone(), keys.clone()),
+            user_login_two_factor: user::UserLoginTwoFactorHandler::new(
+                database.clone(),
+                rds.clone(),
+                keys.clone(),
+            ),
+            user_refresh: user::UserRefreshHandler::new(
+                database.clone(),
+                rds.clone(),
+                keys.clone(),
+            ),
+            user_oidc_login: user::UserOidcLoginHandler::new(database.clone(), rds.clone()),
+            user_oidc_callback: user::UserOidcCallbackHandler::new(
+                database.clone(),
+                rds.clone(),
+                keys.clone(),
+            ),
+            user_reset_password: user::UserResetPasswordHandler::new(
+                database.clone(),
+                rds.clone(),
+                mailer.clone(),
+            ),
+            user_reset_password_confirm: user::UserResetPasswordConfirmHandler::new(
+                database.clone(),
+                rds.clone(),
+            ),
             user_info: user::UserInfoHandler::new(database.clone()),
+            user_update: user::UserUpdateHandler::new(
+                database.clone(),
+                rds.clone(),
+                mailer.clone(),
+            ),
+            user_change_password: user::UserChangePasswordHandler::new(
+                database.clone(),
+                rds.clone(),
+            ),
+            user_two_factor_enroll: user::UserTwoFactorEnrollHandler::new(
+                database.clone(),
+                rds.clone(),
+            ),
+            user_two_factor_confirm: user::UserTwoFactorConfirmHandler::new(
+                database.clone(),
+                rds.clone(),
+            ),
+            user_two_factor_disable: user::UserTwoFactorDisableHandler::new(
+                database.clone(),
+                rds.clone(),
+            ),
+            user_export: user::UserExportHandler::new(database.clone(), rds.clone()),
+            user_delete: user::UserDeleteHandler::new(
+                database.clone(),
+                rds.clone(),
+                ps_manager.clone(),
+                s3_client.clone(),
+            ),
+            user_logout: user::UserLogoutHandler::new(rds.clone()),
+            user_logout_all: user::UserLogoutAllHandler::new(rds.clone()),
+            user_sessions: user::UserSessionListHandler::new(rds.clone()),
+            user_session_delete: user::UserSessionDeleteHandler::new(rds.clone()),
+            user_list: user::GetUserListHandler::new(database.clone()),
+            user_set_role: user::UserSetRoleHandler::new(database.clone(), rds.clone()),
+            user_clear_lockout: user::UserClearLockoutHandler::new(database.clone(), rds.clone()),
 
             ws_ticket: user::UserGetWsTokenHandler::new(rds.clone()),
+            jwks: user::JwksHandler::new(keys.clone()),
 
             ingredient_list: ingredient::GetIngredientListHandler::new(database.clone()),
 
             tag_list: tag::GetTagListHandler::new(database.clone()),
 
             store_list: store::GetStoreListHandler::new(database.clone()),
+            invite_create: store::CreateInviteHandler::new(database.clone()),
+            invite_list: store::GetInviteListHandler::new(database.clone()),
+            invite_revoke: store::RevokeInviteHandler::new(database.clone()),
+            accept_mode_get: store::GetAcceptModeHandler::new(database.clone()),
+            accept_mode_set: store::SetAcceptModeHandler::new(database.clone(), rds.clone()),
+            kitchen_get: store::GetKitchenHandler::new(database.clone()),
+            kitchen_set: store::SetKitchenHandler::new(database.clone()),
 
             pizza_create: pizza::CreatePizzaHandler::new(
                 database.clone(),
@@ -61,6 +187,41 @@ impl Handlers {
                 s3_client.clone(),
             ),
             pizza_list: pizza::GetPizzaListHandler::new(database.clone()),
+            pizza_history: pizza::GetPizzaHistoryHandler::new(database.clone()),
+            pizza_get: pizza::GetPizzaHandler::new(database.clone()),
+            pizza_queue: pizza::GetPizzaQueueHandler::new(database.clone()),
+            pizza_quote: pizza::QuotePizzaHandler::new(database.clone()),
+            pizza_update: pizza::UpdatePizzaHandler::new(
+                database.clone(),
+                rds.clone(),
+                ps_manager.clone(),
+                s3_client.clone(),
+            ),
+            pizza_delete: pizza::DeletePizzaHandler::new(
+                database.clone(),
+                rds.clone(),
+                ps_manager.clone(),
+            ),
+            pizza_restore: pizza::RestorePizzaHandler::new(
+                database.clone(),
+                rds.clone(),
+                ps_manager.clone(),
+            ),
+            pizza_set_status: pizza::SetPizzaStatusHandler::new(
+                database.clone(),
+                rds.clone(),
+                ps_manager.clone(),
+            ),
+            pizza_accept: pizza::AcceptPizzaHandler::new(
+                database.clone(),
+                rds.clone(),
+                ps_manager.clone(),
+            ),
+            pizza_reject: pizza::RejectPizzaHandler::new(
+                database.clone(),
+                rds.clone(),
+                ps_manager.clone(),
+            ),
 
             index_handler: IndexHandler::new(),
         }
@@ -83,12 +244,50 @@ impl Handler for IndexHandler {
     }
 }
 
+#[derive(Serialize)]
+struct SuccessResponse {
+    success: bool,
+}
+
 #[derive(Serialize)]
 struct ErrorResponse {
     success: bool,
     error: String,
 }
 
+fn too_many_requests(retry_after: i64) -> Response {
+    let response = ErrorResponse {
+        success: false,
+        error: format!("Too many attempts, try again in {}s", retry_after),
+    };
+    let mut res = Response::with((
+        status::TooManyRequests,
+        serde_json::to_string(&response).unwrap_or_default(),
+    ));
+    res.headers
+        .set_raw("Retry-After", vec![retry_after.to_string().into_bytes()]);
+    res
+}
+
+// Address of client, proxy appends it to the end of X-Forwarded-For,
+// so preceding entries are sent by client itself and cannot be trusted
+fn client_ip(req: &Request) -> String {
+    let forwarded = trusted_proxy_header().and_then(|header| {
+        req.headers.get_raw(header.as_ref()).and_then(|values| {
+            values
+                .iter()
+                .filter_map(|value| str::from_utf8(value).ok())
+                .flat_map(|value| value.split(','))
+                .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+                .next_back()
+        })
+    });
+    match forwarded {
+        Some(ip) => ip.to_string(),
+        None => req.remote_addr.ip().to_string(),
+    }
+}
+
 #[derive(Serialize)]
 struct ErrorResponseWithValidation {
     success: bool,
@@ -1,23 +1,25 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::io::Read;
 use postgres::Connection;
+use router::Router;
 use iron::headers::ContentType;
 use iron::mime::Mime;
 use iron::mime::TopLevel::Multipart;
 use iron::mime::SubLevel::FormData;
 use iron::{headers, status, Handler, IronResult, Plugin, Request, Response};
 use serde_json;
-use models::pizza::{CreatePizzaInput, Pizza, PizzaListOutput};
-use std::error::Error;
-use utils::s3_uploader::put_object_with_filename;
+use models::pizza::{history_order, CreatePizzaInput, Pizza, PizzaFilter, PizzaListOutput,
+                    PizzaStatus, UpdatePizzaInput};
+use std::str::FromStr;
+use utils::s3_uploader::{put_object_with_filename, PIZZA_IMAGES_BUCKET};
 use utils::validator::{validate_image, validate_pizza_size, ValidationFile};
 use params::{Map, Params, Value};
 use rusoto_s3::S3Client;
-use std::str::FromStr;
 use chrono::DateTime;
 use chrono::offset::Utc;
 use uuid;
 use validator::{Validate, ValidationError};
-use utils::calculator::{calculate_pizza_price, calculate_preparation_time};
+use utils::calculator::{calculate_pizza_price, calculate_price_breakdown, IngredientPrice};
 use models::ingredient::Ingredient;
 use models::tag::Tag;
 use std::fs::File;
@@ -25,9 +27,11 @@ use multipart::server::Entries;
 use utils::types::StringError;
 use std::thread;
 use utils::pubsub::{Manager, PubSubEvent};
-use utils::constants::{CREATE_PIZZA_EVENT_NAME, NOTIFICATION_THREAD_NAME};
+use utils::constants::{CREATE_PIZZA_EVENT_NAME, NOTIFICATION_THREAD_NAME,
+                       RESTORE_PIZZA_EVENT_NAME, UPDATE_PIZZA_EVENT_NAME};
+use utils::jwt::AuthContext;
+use models::user::Role;
 use redis;
-use redis::Commands;
 
 #[derive(Validate)]
 struct CreatePizzaData {
@@ -50,13 +54,13 @@ struct CreateResponse {
 }
 
 #[derive(Serialize)]
-struct CreatePizzaNotification<'a> {
+struct PizzaNotification<'a> {
     store_id: i32,
-    payload: CreatePizzaNotificationPayload<'a>,
+    payload: PizzaNotificationPayload<'a>,
 }
 
 #[derive(Serialize)]
-struct CreatePizzaNotificationPayload<'a> {
+struct PizzaNotificationPayload<'a> {
     event_name: &'a str,
     data: PizzaListOutput,
 }
@@ -98,8 +102,9 @@ impl Handler for CreatePizzaHandler {
                 return Ok(Response::with((status::BadRequest, res)));
             }
         };
-        let store_id = try_store_id!(req.headers);
-        let user_uuid = try_handler!(uuid::Uuid::from_str(try_user_uuid!(req.headers).as_ref()));
+        let auth = try_auth!(req);
+        let store_id = auth.store_id;
+        let user_uuid = auth.uuid;
         let entries = try_handler!(req.extensions.get_mut::<Entries>().ok_or(StringError(
             "Cannot extract multipart form fields".to_string()
         )));
@@ -124,19 +129,22 @@ impl Handler for CreatePizzaHandler {
         );
         let s3_client = self.s3_client.lock().unwrap();
         let uid = uuid::Uuid::new_v4();
-        let uid_to_find = uid.clone();
+        let uid_to_find = uid;
         let name = format!("{}_pizza.png", uid);
         let file = File::open(create_pizza_data.image.file.path.clone()).unwrap();
         try_handler!(put_object_with_filename(
             &s3_client,
-            "pizza-kottans",
+            PIZZA_IMAGES_BUCKET,
             file,
             name.as_ref()
         ));
-        let time_prepared = calculate_preparation_time(
+        let time_prepared = try_handler!(Pizza::estimate_time_prepared(
+            &db,
+            store_id,
             &create_pizza_data.size,
             create_pizza_data.ingredients.len(),
-        );
+            None
+        ));
         let input = CreatePizzaInput {
             uuid: uid,
             name: create_pizza_data.name,
@@ -151,7 +159,7 @@ impl Handler for CreatePizzaHandler {
             description: create_pizza_data.description,
             tags: create_pizza_data.tags,
             img_url: format!("static/upload/{}", name),
-            time_prepared: time_prepared.clone(),
+            time_prepared,
             ingredients: create_pizza_data.ingredients,
         };
         try_handler!(Pizza::create(&db, input));
@@ -162,15 +170,13 @@ impl Handler for CreatePizzaHandler {
             let db = mx.lock().unwrap();
             let ps_manager = ps.lock().unwrap();
             let red = rds.lock().unwrap();
-            let red_value = uid_to_find.to_string() + "@" + &format!("{:?}", time_prepared);
-            if let Err(e) = red.lpush::<String, String, i32>("pizza-created".to_string(), red_value)
-            {
+            if let Err(e) = Pizza::push_to_queue(&red, uid_to_find, time_prepared) {
                 println!("Redis create pizza list push error: {:?}", e)
             };
             if let Some(p) = Pizza::get_pizza_by_uuid(&db, uid_to_find, store_id) {
-                let event = CreatePizzaNotification {
+                let event = PizzaNotification {
                     store_id,
-                    payload: CreatePizzaNotificationPayload {
+                    payload: PizzaNotificationPayload {
                         event_name: CREATE_PIZZA_EVENT_NAME,
                         data: p,
                     },
@@ -195,7 +201,7 @@ fn process_entries(entries: &mut Entries) -> Option<CreatePizzaData> {
     Some(CreatePizzaData {
         image: match entries.files.get_mut("image") {
             Some(files) => {
-                if files.len() == 0 {
+                if files.is_empty() {
                     return None;
                 };
                 ValidationFile {
@@ -208,10 +214,7 @@ fn process_entries(entries: &mut Entries) -> Option<CreatePizzaData> {
             Some(field) => field.to_owned(),
             _ => return None,
         },
-        description: match entries.fields.get("description") {
-            Some(field) => Some(field.to_owned()),
-            _ => None,
-        },
+        description: entries.fields.get("description").map(|field| field.to_owned()),
         size: match entries.fields.get("size") {
             Some(field) => match field.to_owned().parse::<i64>() {
                 Ok(n) => n,
@@ -250,14 +253,14 @@ impl GetPizzaListHandler {
 impl Handler for GetPizzaListHandler {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
         req.headers.remove::<headers::ContentType>();
-        let store_id = try_store_id!(req.headers);
+        let store_id = try_store_id!(req);
         let map: &Map = try_handler!(req.get_ref::<Params>());
         let offset = match map.find(&["offset"]) {
-            Some(&Value::String(ref s)) => s.to_owned().parse::<i64>().ok(),
+            Some(Value::String(s)) => s.to_owned().parse::<i64>().ok(),
             _ => None,
         };
         let limit = match map.find(&["limit"]) {
-            Some(&Value::String(ref s)) => s.to_owned().parse::<i64>().ok(),
+            Some(Value::String(s)) => s.to_owned().parse::<i64>().ok(),
             _ => None,
         };
         let mg = self.database.lock().unwrap();
@@ -266,3 +269,802 @@ impl Handler for GetPizzaListHandler {
         Ok(Response::with((status::Ok, res)))
     }
 }
+
+// Get pizza history
+pub struct GetPizzaHistoryHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl GetPizzaHistoryHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> GetPizzaHistoryHandler {
+        GetPizzaHistoryHandler { database }
+    }
+}
+
+// Url param parsed to T, Err with param name when it is malformed
+fn url_param<T: FromStr>(map: &Map, name: &'static str) -> Result<Option<T>, &'static str> {
+    match map.find(&[name]) {
+        Some(Value::String(s)) => match s.parse::<T>() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(name),
+        },
+        Some(_) => Err(name),
+        None => Ok(None),
+    }
+}
+
+// Non-negative number, like offset or limit
+fn url_count_param(map: &Map, name: &'static str) -> Result<Option<i64>, &'static str> {
+    match url_param::<i64>(map, name)? {
+        Some(count) if count < 0 => Err(name),
+        count => Ok(count),
+    }
+}
+
+// Comma separated list of ids, like "1,5,3", repeated ids are dropped
+// as pizza is matched by number of distinct ids
+fn url_ids_param(map: &Map, name: &'static str) -> Result<Option<Vec<i32>>, &'static str> {
+    match url_param::<String>(map, name)? {
+        Some(s) => match s.split(',')
+            .map(|id| id.trim().parse::<i32>())
+            .collect::<Result<Vec<i32>, _>>()
+        {
+            Ok(mut ids) => {
+                ids.sort();
+                ids.dedup();
+                Ok(Some(ids))
+            }
+            Err(_) => Err(name),
+        },
+        None => Ok(None),
+    }
+}
+
+// Filter, order, limit and offset of history request
+type HistoryParams = (PizzaFilter, &'static str, Option<i64>, Option<i64>);
+
+fn history_params(map: &Map) -> Result<HistoryParams, &'static str> {
+    let order = match url_param::<String>(map, "sort")? {
+        Some(sort) => match history_order(sort.as_ref()) {
+            Some(order) => order,
+            None => return Err("sort"),
+        },
+        None => "created_date DESC",
+    };
+    let filter = PizzaFilter {
+        status: url_param(map, "status")?,
+        accepted: url_param(map, "accepted")?,
+        user_uuid: url_param(map, "user_uuid")?,
+        tags: url_ids_param(map, "tags")?,
+        ingredients: url_ids_param(map, "ingredients")?,
+        size: url_param(map, "size")?,
+        price_from: url_param(map, "price_from")?,
+        price_to: url_param(map, "price_to")?,
+        created_from: url_param(map, "created_from")?,
+        created_to: url_param(map, "created_to")?,
+        prepared_from: url_param(map, "prepared_from")?,
+        prepared_to: url_param(map, "prepared_to")?,
+    };
+    let offset = url_count_param(map, "offset")?;
+    let limit = url_count_param(map, "limit")?;
+    Ok((filter, order, offset, limit))
+}
+
+impl Handler for GetPizzaHistoryHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        req.headers.remove::<headers::ContentType>();
+        let store_id = try_store_id!(req);
+        let map: &Map = try_handler!(req.get_ref::<Params>());
+        let (filter, order, offset, limit) = match history_params(map) {
+            Ok(params) => params,
+            Err(name) => {
+                return pizza_error(
+                    format!("Url param {} is not valid", name).as_ref(),
+                    status::BadRequest,
+                )
+            }
+        };
+        let mg = self.database.lock().unwrap();
+        let response = try_handler!(Pizza::get_history(
+            &mg,
+            &filter,
+            order,
+            offset,
+            limit,
+            store_id
+        ));
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Get single pizza
+pub struct GetPizzaHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl GetPizzaHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> GetPizzaHandler {
+        GetPizzaHandler { database }
+    }
+}
+
+// Only rejected one of deleted pizzas is got, it is shown to its author and kitchen
+fn can_see_pizza(auth: &AuthContext, pizza: &Pizza) -> bool {
+    !pizza.deleted || auth.role.allows(Role::Cook) || pizza.user_uuid == auth.uuid
+}
+
+impl Handler for GetPizzaHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let uuid = try_handler!(
+            uuid::Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let mg = self.database.lock().unwrap();
+        match try_handler!(Pizza::get(&mg, uuid, auth.store_id)) {
+            Some(ref pizza) if can_see_pizza(&auth, pizza) => {
+                let res: String = try_handler!(serde_json::to_string(pizza));
+                Ok(Response::with((status::Ok, res)))
+            }
+            _ => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Pizza not found".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                Ok(Response::with((status::NotFound, res)))
+            }
+        }
+    }
+}
+
+// Only author of pizza or admin of its store may change it
+fn can_manage_pizza(auth: &AuthContext, author: uuid::Uuid) -> bool {
+    auth.role == Role::Admin || author == auth.uuid
+}
+
+fn pizza_error(error: &str, status: status::Status) -> IronResult<Response> {
+    let response = super::ErrorResponse {
+        success: false,
+        error: error.to_string(),
+    };
+    let res: String = try_handler!(serde_json::to_string(&response));
+    Ok(Response::with((status, res)))
+}
+
+// Price and preparation time of pizza before it is created
+pub struct QuotePizzaHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl QuotePizzaHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> QuotePizzaHandler {
+        QuotePizzaHandler { database }
+    }
+}
+
+#[derive(Validate, Deserialize)]
+struct QuotePizzaRequest {
+    #[validate(custom(function = "validate_pizza_size",
+                      message = "Pizza size can be either 30, 45 or 60"))]
+    size: i64,
+    ingredients: Vec<i32>,
+    #[serde(default)]
+    tags: Vec<i32>,
+}
+
+#[derive(Serialize)]
+struct QuoteResponse {
+    success: bool,
+    crust: f64,
+    ingredients: Vec<IngredientPrice>,
+    total: f64,
+    time_prepared: DateTime<Utc>,
+}
+
+impl Handler for QuotePizzaHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let quote_data: QuotePizzaRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let db = self.database.lock().unwrap();
+        try_validate!(
+            quote_data.validate(),
+            vec![
+                Ingredient::validate_ingredients_exist(&db, &quote_data.ingredients),
+                Tag::validate_tags_exist(&db, &quote_data.tags),
+            ]
+        );
+        let breakdown = try_handler!(calculate_price_breakdown(
+            &db,
+            &quote_data.ingredients,
+            &quote_data.size
+        ));
+        let time_prepared = try_handler!(Pizza::estimate_time_prepared(
+            &db,
+            store_id,
+            &quote_data.size,
+            quote_data.ingredients.len(),
+            None
+        ));
+        let response = QuoteResponse {
+            success: true,
+            crust: breakdown.crust,
+            ingredients: breakdown.ingredients,
+            total: breakdown.total,
+            time_prepared,
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Pizza place in acceptance queue
+pub struct GetPizzaQueueHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl GetPizzaQueueHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> GetPizzaQueueHandler {
+        GetPizzaQueueHandler { database }
+    }
+}
+
+impl Handler for GetPizzaQueueHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let uuid = try_handler!(
+            uuid::Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let db = self.database.lock().unwrap();
+        match try_handler!(Pizza::get_queue_position(&db, uuid, store_id)) {
+            Some(position) => {
+                let res: String = try_handler!(serde_json::to_string(&position));
+                Ok(Response::with((status::Ok, res)))
+            }
+            None => pizza_error("Pizza is not in queue", status::NotFound),
+        }
+    }
+}
+
+// Delete pizza
+pub struct DeletePizzaHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    ps_manager: Arc<Mutex<Manager>>,
+}
+
+impl DeletePizzaHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        ps_manager: Arc<Mutex<Manager>>,
+    ) -> DeletePizzaHandler {
+        DeletePizzaHandler {
+            database,
+            rds,
+            ps_manager,
+        }
+    }
+}
+
+impl Handler for DeletePizzaHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let uuid = try_handler!(
+            uuid::Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let db = self.database.lock().unwrap();
+        let pizza = match Pizza::get_pizza_by_uuid(&db, uuid, auth.store_id) {
+            Some(ref pizza) if pizza.status == PizzaStatus::Cancelled => {
+                return pizza_error("Pizza not found", status::NotFound)
+            }
+            Some(ref pizza) if !can_manage_pizza(&auth, pizza.user_uuid) => {
+                return pizza_error(
+                    "Pizza can be deleted only by its author or admin",
+                    status::Forbidden,
+                )
+            }
+            Some(pizza) => pizza,
+            None => return pizza_error("Pizza not found", status::NotFound),
+        };
+        move_pizza(
+            &db,
+            &self.rds,
+            &self.ps_manager,
+            &pizza,
+            PizzaStatus::Cancelled,
+            None,
+        )
+    }
+}
+
+// Moves pizza to next status, takes it out of acceptance queue
+// when it is ready, cancelled or rejected and notifies store clients
+fn move_pizza(
+    db: &MutexGuard<Connection>,
+    rds: &Arc<Mutex<redis::Connection>>,
+    ps_manager: &Arc<Mutex<Manager>>,
+    pizza: &PizzaListOutput,
+    next: PizzaStatus,
+    reason: Option<&str>,
+) -> IronResult<Response> {
+    if !pizza.status.can_become(next)
+        || !try_handler!(Pizza::set_status_with_reason(
+            db,
+            pizza.uuid,
+            pizza.store_id,
+            next,
+            reason
+        )) {
+        return pizza_error(
+            format!(
+                "Pizza cannot become {} being {}",
+                next.as_str(),
+                pizza.status.as_str()
+            ).as_ref(),
+            status::Conflict,
+        );
+    };
+    if next == PizzaStatus::Ready || next.is_dropped() {
+        let rds = rds.lock().unwrap();
+        try_handler!(Pizza::remove_from_queue(&rds, pizza.uuid));
+    };
+    Pizza::emit_pizzas_event(
+        next.event_name(),
+        vec![(pizza.uuid.to_string(), pizza.store_id)],
+        ps_manager.clone(),
+    );
+    let response = super::SuccessResponse { success: true };
+    let res: String = try_handler!(serde_json::to_string(&response));
+    Ok(Response::with((status::Ok, res)))
+}
+
+// Change pizza status
+pub struct SetPizzaStatusHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    ps_manager: Arc<Mutex<Manager>>,
+}
+
+impl SetPizzaStatusHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        ps_manager: Arc<Mutex<Manager>>,
+    ) -> SetPizzaStatusHandler {
+        SetPizzaStatusHandler {
+            database,
+            rds,
+            ps_manager,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SetPizzaStatusRequest {
+    status: PizzaStatus,
+}
+
+impl Handler for SetPizzaStatusHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let uuid = try_handler!(
+            uuid::Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let status_data: SetPizzaStatusRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let db = self.database.lock().unwrap();
+        let pizza = match Pizza::get_pizza_by_uuid(&db, uuid, store_id) {
+            Some(pizza) => pizza,
+            None => return pizza_error("Pizza not found", status::NotFound),
+        };
+        move_pizza(
+            &db,
+            &self.rds,
+            &self.ps_manager,
+            &pizza,
+            status_data.status,
+            None,
+        )
+    }
+}
+
+// Accept pizza by kitchen staff
+pub struct AcceptPizzaHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    ps_manager: Arc<Mutex<Manager>>,
+}
+
+impl AcceptPizzaHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        ps_manager: Arc<Mutex<Manager>>,
+    ) -> AcceptPizzaHandler {
+        AcceptPizzaHandler {
+            database,
+            rds,
+            ps_manager,
+        }
+    }
+}
+
+impl Handler for AcceptPizzaHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let uuid = try_handler!(
+            uuid::Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let db = self.database.lock().unwrap();
+        let pizza = match Pizza::get_pizza_by_uuid(&db, uuid, store_id) {
+            Some(pizza) => pizza,
+            None => return pizza_error("Pizza not found", status::NotFound),
+        };
+        move_pizza(
+            &db,
+            &self.rds,
+            &self.ps_manager,
+            &pizza,
+            PizzaStatus::Ready,
+            None,
+        )
+    }
+}
+
+// Reject pizza by kitchen staff
+pub struct RejectPizzaHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    ps_manager: Arc<Mutex<Manager>>,
+}
+
+impl RejectPizzaHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        ps_manager: Arc<Mutex<Manager>>,
+    ) -> RejectPizzaHandler {
+        RejectPizzaHandler {
+            database,
+            rds,
+            ps_manager,
+        }
+    }
+}
+
+#[derive(Validate, Deserialize, Default)]
+struct RejectPizzaRequest {
+    #[validate(length(max = "1000",
+                      message = "Reject reason is not valid. Max length is 1000"))]
+    reason: Option<String>,
+}
+
+impl Handler for RejectPizzaHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let uuid = try_handler!(
+            uuid::Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        // reason is optional, so is the whole body
+        let reject_data: RejectPizzaRequest = if payload.trim().is_empty() {
+            RejectPizzaRequest::default()
+        } else {
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest)
+        };
+        try_validate!(reject_data.validate());
+        let db = self.database.lock().unwrap();
+        let pizza = match Pizza::get_pizza_by_uuid(&db, uuid, store_id) {
+            Some(pizza) => pizza,
+            None => return pizza_error("Pizza not found", status::NotFound),
+        };
+        move_pizza(
+            &db,
+            &self.rds,
+            &self.ps_manager,
+            &pizza,
+            PizzaStatus::Rejected,
+            reject_data.reason.as_ref().map(|reason| reason.as_ref()),
+        )
+    }
+}
+
+// Restore deleted pizza
+pub struct RestorePizzaHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    ps_manager: Arc<Mutex<Manager>>,
+}
+
+impl RestorePizzaHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        ps_manager: Arc<Mutex<Manager>>,
+    ) -> RestorePizzaHandler {
+        RestorePizzaHandler {
+            database,
+            rds,
+            ps_manager,
+        }
+    }
+}
+
+impl Handler for RestorePizzaHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let uuid = try_handler!(
+            uuid::Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let db = self.database.lock().unwrap();
+        match Pizza::get_pizza_by_uuid(&db, uuid, auth.store_id) {
+            Some(ref pizza) if !can_manage_pizza(&auth, pizza.user_uuid) => {
+                return pizza_error(
+                    "Pizza can be restored only by its author or admin",
+                    status::Forbidden,
+                )
+            }
+            Some(_) => (),
+            None => return pizza_error("Pizza not found", status::NotFound),
+        };
+        if try_handler!(Pizza::restore(&db, uuid, auth.store_id)).is_none() {
+            return pizza_error("Deleted pizza not found", status::NotFound);
+        };
+        let pizza = match Pizza::get_pizza_by_uuid(&db, uuid, auth.store_id) {
+            Some(pizza) => pizza,
+            None => return pizza_error("Pizza not found", status::NotFound),
+        };
+        if !pizza.accepted {
+            // pizza prepared meanwhile is accepted on next check
+            let rds = self.rds.lock().unwrap();
+            try_handler!(Pizza::push_to_queue(&rds, uuid, pizza.time_prepared));
+        };
+        {
+            let ps_manager = self.ps_manager.lock().unwrap();
+            let event = PizzaNotification {
+                store_id: auth.store_id,
+                payload: PizzaNotificationPayload {
+                    event_name: RESTORE_PIZZA_EVENT_NAME,
+                    data: pizza,
+                },
+            };
+            ps_manager.send(PubSubEvent {
+                channel: NOTIFICATION_THREAD_NAME.to_string(),
+                message: try_handler!(serde_json::to_string(&event)),
+            });
+        }
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Update pizza
+pub struct UpdatePizzaHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    ps_manager: Arc<Mutex<Manager>>,
+    s3_client: Arc<Mutex<S3Client>>,
+}
+
+impl UpdatePizzaHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        ps_manager: Arc<Mutex<Manager>>,
+        s3_client: Arc<Mutex<S3Client>>,
+    ) -> UpdatePizzaHandler {
+        UpdatePizzaHandler {
+            database,
+            rds,
+            ps_manager,
+            s3_client,
+        }
+    }
+}
+
+// Fields missing in form data are left as they are
+struct PizzaPatch {
+    image: Option<ValidationFile>,
+    name: Option<String>,
+    size: Option<i64>,
+    description: Option<String>,
+    tags: Option<Vec<i32>>,
+    ingredients: Option<Vec<i32>>,
+}
+
+#[derive(Validate)]
+struct UpdatePizzaData {
+    #[validate(length(min = "3", max = "24",
+                      message = "Pizza name is not valid. Min length is 3, max - is 24"))]
+    name: String,
+    #[validate(custom(function = "validate_pizza_size",
+                      message = "Pizza size can be either 30, 45 or 60"))]
+    size: i64,
+    description: Option<String>,
+    tags: Vec<i32>,
+    ingredients: Vec<i32>,
+}
+
+#[derive(Serialize)]
+struct UpdateResponse {
+    success: bool,
+    price: f64,
+    time_prepared: DateTime<Utc>,
+}
+
+impl Handler for UpdatePizzaHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        match req.headers.get() {
+            Some(&ContentType(Mime(Multipart, FormData, _))) => (),
+            _ => return pizza_error("Wrong Content-Type", status::BadRequest),
+        };
+        let auth = try_auth!(req);
+        let store_id = auth.store_id;
+        let uuid = try_handler!(
+            uuid::Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let entries = try_handler!(req.extensions.get_mut::<Entries>().ok_or(StringError(
+            "Cannot extract multipart form fields".to_string()
+        )));
+        let patch = match process_patch_entries(entries) {
+            Some(patch) => patch,
+            None => {
+                return pizza_error("Field(s) in form data are malformed", status::BadRequest)
+            }
+        };
+        let db = self.database.lock().unwrap();
+        let pizza = match try_handler!(Pizza::get(&db, uuid, store_id)) {
+            Some(pizza) => pizza,
+            None => return pizza_error("Pizza not found", status::NotFound),
+        };
+        if !can_manage_pizza(&auth, pizza.user_uuid) {
+            return pizza_error(
+                "Pizza can be changed only by its author or admin",
+                status::Forbidden,
+            );
+        };
+        if pizza.accepted {
+            return pizza_error("Accepted pizza cannot be changed", status::Conflict);
+        };
+        let tag_ids: Vec<i32> = pizza.tags.iter().map(|tag| tag.id).collect();
+        let ingredient_ids: Vec<i32> = pizza
+            .ingredients
+            .iter()
+            .map(|ingredient| ingredient.id)
+            .collect();
+        let update_pizza_data = UpdatePizzaData {
+            name: patch.name.unwrap_or(pizza.name),
+            size: patch.size.unwrap_or(pizza.size as i64),
+            description: patch.description.or(pizza.description),
+            tags: patch.tags.unwrap_or(tag_ids),
+            ingredients: patch.ingredients.unwrap_or(ingredient_ids),
+        };
+        try_validate!(
+            update_pizza_data.validate(),
+            vec![
+                match patch.image {
+                    Some(ref image) => validate_image(image),
+                    None => Ok(()),
+                },
+                Ingredient::validate_ingredients_exist(&db, &update_pizza_data.ingredients),
+                Tag::validate_tags_exist(&db, &update_pizza_data.tags),
+            ]
+        );
+        let price = try_handler!(calculate_pizza_price(
+            &db,
+            &update_pizza_data.ingredients,
+            &update_pizza_data.size
+        ));
+        let time_prepared = try_handler!(Pizza::estimate_time_prepared(
+            &db,
+            store_id,
+            &update_pizza_data.size,
+            update_pizza_data.ingredients.len(),
+            Some(uuid)
+        ));
+        let input = UpdatePizzaInput {
+            uuid,
+            name: update_pizza_data.name,
+            store_id,
+            price,
+            size: update_pizza_data.size as i32,
+            description: update_pizza_data.description,
+            tags: update_pizza_data.tags,
+            ingredients: update_pizza_data.ingredients,
+            time_prepared,
+        };
+        if !try_handler!(Pizza::update(&db, input)) {
+            // accepted or deleted while request was processed
+            return pizza_error("Accepted pizza cannot be changed", status::Conflict);
+        };
+        if let Some(image) = patch.image {
+            // new picture replaces old one under the same name,
+            // only once pizza is known to be changeable
+            let s3_client = self.s3_client.lock().unwrap();
+            let name = format!("{}_pizza.png", uuid);
+            let file = File::open(image.file.path.clone()).unwrap();
+            try_handler!(put_object_with_filename(
+                &s3_client,
+                PIZZA_IMAGES_BUCKET,
+                file,
+                name.as_ref()
+            ));
+        };
+        {
+            let rds = self.rds.lock().unwrap();
+            try_handler!(Pizza::remove_from_queue(&rds, uuid));
+            try_handler!(Pizza::push_to_queue(&rds, uuid, time_prepared));
+        }
+        if let Some(p) = Pizza::get_pizza_by_uuid(&db, uuid, store_id) {
+            let ps_manager = self.ps_manager.lock().unwrap();
+            let event = PizzaNotification {
+                store_id,
+                payload: PizzaNotificationPayload {
+                    event_name: UPDATE_PIZZA_EVENT_NAME,
+                    data: p,
+                },
+            };
+            ps_manager.send(PubSubEvent {
+                channel: NOTIFICATION_THREAD_NAME.to_string(),
+                message: try_handler!(serde_json::to_string(&event)),
+            });
+        };
+        let response = UpdateResponse {
+            success: true,
+            price,
+            time_prepared,
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+fn process_patch_entries(entries: &mut Entries) -> Option<PizzaPatch> {
+    Some(PizzaPatch {
+        image: match entries.files.get_mut("image") {
+            Some(files) if !files.is_empty() => Some(ValidationFile {
+                file: files.remove(0),
+            }),
+            _ => None,
+        },
+        name: entries.fields.get("name").map(|field| field.to_owned()),
+        description: entries.fields.get("description").map(|field| field.to_owned()),
+        size: match entries.fields.get("size") {
+            Some(field) => match field.parse::<i64>() {
+                Ok(n) => Some(n),
+                _ => return None,
+            },
+            _ => None,
+        },
+        tags: match entries.fields.get("tags") {
+            Some(field) => match serde_json::from_str::<Vec<i32>>(field) {
+                Ok(ids) => Some(ids),
+                _ => return None,
+            },
+            _ => None,
+        },
+        ingredients: match entries.fields.get("ingredients") {
+            Some(field) => match serde_json::from_str::<Vec<i32>>(field) {
+                Ok(ids) => Some(ids),
+                _ => return None,
+            },
+            _ => None,
+        },
+    })
+}
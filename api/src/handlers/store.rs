@@ -1,9 +1,18 @@
 use std::sync::{Arc, Mutex};
 use postgres::Connection;
+use router::Router;
 use iron::{status, Handler, IronResult, Request, Response};
+use std::io::Read;
 use serde_json;
-use models::store::Store;
-use std::error::Error;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use models::store::{AcceptMode, Kitchen, Store};
+use models::pizza::Pizza;
+use redis;
+use models::invite::Invite;
+use models::user::Role;
+use utils::random_token;
+use validator::Validate;
 
 // Get ingredient list
 pub struct GetStoreListHandler {
@@ -24,3 +33,240 @@ impl Handler for GetStoreListHandler {
         Ok(Response::with((status::Ok, res)))
     }
 }
+
+// Create invite code
+pub struct CreateInviteHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl CreateInviteHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> CreateInviteHandler {
+        CreateInviteHandler { database }
+    }
+}
+
+fn default_invite_max_uses() -> i32 {
+    1
+}
+
+fn default_invite_lifetime() -> i64 {
+    7 * 24 * 60 * 60
+}
+
+#[derive(Validate, Deserialize)]
+struct CreateInviteRequest {
+    #[serde(default = "default_invite_max_uses")]
+    #[validate(range(min = "1", max = "1000",
+                     message = "Max uses is not valid. Min is 1, max - is 1000"))]
+    max_uses: i32,
+    // lifetime in seconds
+    #[serde(default = "default_invite_lifetime")]
+    #[validate(range(min = "60", max = "2592000",
+                     message = "Invite lifetime is not valid. Min is 1 minute, max - is 30 days"))]
+    expires_in: i64,
+    role: Option<Role>,
+}
+
+impl Handler for CreateInviteHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let invite_data: CreateInviteRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        try_validate!(invite_data.validate());
+        let mg = self.database.lock().unwrap();
+        let invite = try_handler!(Invite::new(
+            &mg,
+            auth.store_id,
+            random_token().as_ref(),
+            invite_data.role,
+            invite_data.max_uses,
+            Utc::now() + Duration::seconds(invite_data.expires_in),
+            auth.uuid,
+        ));
+        let res: String = try_handler!(serde_json::to_string(&invite));
+        Ok(Response::with((status::Created, res)))
+    }
+}
+
+// Store invite codes list
+pub struct GetInviteListHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl GetInviteListHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> GetInviteListHandler {
+        GetInviteListHandler { database }
+    }
+}
+
+impl Handler for GetInviteListHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let mg = self.database.lock().unwrap();
+        let response = try_handler!(Invite::get_all(&mg, store_id));
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Revoke invite code
+pub struct RevokeInviteHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl RevokeInviteHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> RevokeInviteHandler {
+        RevokeInviteHandler { database }
+    }
+}
+
+impl Handler for RevokeInviteHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let uuid = try_handler!(
+            Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let mg = self.database.lock().unwrap();
+        if try_handler!(Invite::revoke(&mg, uuid, store_id)) {
+            let response = super::SuccessResponse { success: true };
+            let res: String = try_handler!(serde_json::to_string(&response));
+            Ok(Response::with((status::Ok, res)))
+        } else {
+            let response = super::ErrorResponse {
+                success: false,
+                error: "Invite not found".to_string(),
+            };
+            let res: String = try_handler!(serde_json::to_string(&response));
+            Ok(Response::with((status::NotFound, res)))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AcceptModeData {
+    accept_mode: AcceptMode,
+}
+
+// Get store accept mode
+pub struct GetAcceptModeHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl GetAcceptModeHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> GetAcceptModeHandler {
+        GetAcceptModeHandler { database }
+    }
+}
+
+impl Handler for GetAcceptModeHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let mg = self.database.lock().unwrap();
+        let response = AcceptModeData {
+            accept_mode: try_handler!(Store::get_accept_mode(&mg, store_id)),
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Switch store between automatic and manual pizza acceptance
+pub struct SetAcceptModeHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl SetAcceptModeHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> SetAcceptModeHandler {
+        SetAcceptModeHandler { database, rds }
+    }
+}
+
+impl Handler for SetAcceptModeHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let mode_data: AcceptModeData =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        try_handler!(Store::set_accept_mode(&mg, store_id, mode_data.accept_mode));
+        if mode_data.accept_mode == AcceptMode::Auto {
+            // pizzas which came due in manual mode were dropped from queue
+            let rds = self.rds.lock().unwrap();
+            try_handler!(Pizza::requeue_store(&mg, &rds, store_id));
+        };
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Get store kitchen capacity
+pub struct GetKitchenHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl GetKitchenHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> GetKitchenHandler {
+        GetKitchenHandler { database }
+    }
+}
+
+impl Handler for GetKitchenHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let mg = self.database.lock().unwrap();
+        let response = try_handler!(Store::get_kitchen(&mg, store_id));
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Set store kitchen capacity, new pizzas are estimated with it
+pub struct SetKitchenHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl SetKitchenHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> SetKitchenHandler {
+        SetKitchenHandler { database }
+    }
+}
+
+#[derive(Validate, Deserialize)]
+struct KitchenRequest {
+    #[validate(range(min = "1", max = "100",
+                     message = "Ovens number is not valid. Min is 1, max - is 100"))]
+    ovens: i32,
+    // pizzas per hour
+    #[validate(range(min = "1", max = "3600",
+                     message = "Throughput is not valid. Min is 1, max - is 3600"))]
+    throughput: i32,
+}
+
+impl Handler for SetKitchenHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let kitchen_data: KitchenRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        try_validate!(kitchen_data.validate());
+        let mg = self.database.lock().unwrap();
+        let kitchen = Kitchen {
+            ovens: kitchen_data.ovens,
+            throughput: kitchen_data.throughput,
+        };
+        try_handler!(Store::set_kitchen(&mg, store_id, &kitchen));
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
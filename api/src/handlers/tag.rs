@@ -4,7 +4,6 @@ use iron::{headers, status, Handler, IronResult, Plugin, Request, Response};
 use serde_json;
 use params::{Map, Params, Value};
 use models::tag::Tag;
-use std::error::Error;
 
 // Get tag list
 pub struct GetTagListHandler {
@@ -22,11 +21,11 @@ impl Handler for GetTagListHandler {
         req.headers.remove::<headers::ContentType>();
         let map: &Map = try_handler!(req.get_ref::<Params>());
         let offset = match map.find(&["offset"]) {
-            Some(&Value::String(ref s)) => s.to_owned().parse::<i64>().ok(),
+            Some(Value::String(s)) => s.to_owned().parse::<i64>().ok(),
             _ => None,
         };
         let limit = match map.find(&["limit"]) {
-            Some(&Value::String(ref s)) => s.to_owned().parse::<i64>().ok(),
+            Some(Value::String(s)) => s.to_owned().parse::<i64>().ok(),
             _ => None,
         };
         let mg = self.database.lock().unwrap();
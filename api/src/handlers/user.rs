@@ -1,31 +1,68 @@
-use std::sync::{Arc, Mutex};
-use postgres::Connection;
-use iron::headers::{Authorization, Bearer};
-use iron::{status, Handler, IronResult, Request, Response};
+use std::sync::{Arc, Mutex, MutexGuard};
+use postgres::{self, Connection};
+use iron::headers::UserAgent;
+use router::Router;
+use iron::{status, Handler, IronResult, Plugin, Request, Response};
+use params::{Map, Params, Value};
 use std::io::Read;
-use models::user::User;
-use models::store::Store;
+use models::user::{Role, User};
+use models::store::{Store, UnverifiedAccess};
+use models::pizza::Pizza;
+use models::invite::Invite;
 use uuid::Uuid;
 use serde_json;
 use std::error::Error;
-use utils::types::StringError;
-use utils::jwt::{self, get_claims, Claims};
-use chrono::{DateTime, Duration, Utc};
+use utils::jwt;
+use utils::jwk::{Jwk, KeyStore};
+use chrono::{DateTime, Utc};
 use redis;
-use utils::cache::{set_session, set_ws_ticket, WS_TICKET_EXPIRATION_TIME};
+use rusoto_s3::S3Client;
+use utils::cache::{delete_all_sessions, delete_other_sessions, delete_session, extend_session,
+                   get_session, get_sessions, get_user_ticket, has_session, set_oidc_state,
+                   set_refresh_token, set_session, set_user_ticket, set_ws_ticket,
+                   take_oidc_state, take_user_ticket, use_refresh_token, use_totp_step,
+                   OidcLoginState, RefreshSession, RefreshTokenState, SessionInfo,
+                   EMAIL_VERIFICATION_EXPIRATION_TIME, EMAIL_VERIFICATION_TICKET,
+                   LOGIN_CHALLENGE_EXPIRATION_TIME, LOGIN_CHALLENGE_TICKET,
+                   PASSWORD_RESET_EXPIRATION_TIME, PASSWORD_RESET_TICKET,
+                   WS_TICKET_EXPIRATION_TIME};
+use utils::mailer::{MailResult, Mailer};
+use utils::pubsub::Manager;
+use utils::constants::DELETE_PIZZA_EVENT_NAME;
+use utils::throttle::{clear_failures, locked_for, register_failure, Scope};
+use utils::config::{access_token_lifetime, account_deletion_removes_pizzas,
+                    email_verification_enabled, oidc_auto_provision, refresh_token_lifetime};
+use utils::oidc::{authorization_url, discover, exchange_code, oidc_config, IdTokenClaims};
+use utils::types::StringError;
+use utils::s3_uploader::{delete_object_with_filename, PIZZA_IMAGES_BUCKET};
 use utils::random_token;
+use utils::password::hash_password;
+use utils::totp::{generate_recovery_codes, generate_secret, hash_recovery_code, otpauth_uri,
+                  verify_code};
 
 use validator::{Validate, ValidationError};
+use std::borrow::Cow;
+use std::collections::HashMap;
 
 // Create user
 
 pub struct UserCreateHandler {
     database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    mailer: Arc<Mutex<Box<dyn Mailer>>>,
 }
 
 impl UserCreateHandler {
-    pub fn new(database: Arc<Mutex<Connection>>) -> UserCreateHandler {
-        UserCreateHandler { database }
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        mailer: Arc<Mutex<Box<dyn Mailer>>>,
+    ) -> UserCreateHandler {
+        UserCreateHandler {
+            database,
+            rds,
+            mailer,
+        }
     }
 }
 
@@ -39,15 +76,47 @@ struct CreateUserRequest {
     password: String,
     #[validate(must_match(other = "password", message = "Passwords do not match"))]
     password_repeat: String,
-    store_id: i32,
+    store_id: Option<i32>,
     #[validate(length(min = "8", message = "Store password is not valid. Min length is 8"))]
-    store_password: String,
+    store_password: Option<String>,
+    invite_code: Option<String>,
 }
 
 #[derive(Serialize)]
 struct CreateUserResponse {
     success: bool,
     uuid: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+}
+
+fn validation_error(error: ValidationError) -> IronResult<Response> {
+    let response = super::ErrorResponseWithValidation {
+        success: false,
+        error: "Validation error".to_string(),
+        validations: error.message.iter().map(|msg| msg.to_string()).collect(),
+    };
+    let res: String = try_handler!(serde_json::to_string(&response));
+    Ok(Response::with((status::BadRequest, res)))
+}
+
+// Invite code takes precedence over legacy store password
+fn store_credentials_validation(
+    db: &MutexGuard<Connection>,
+    user_data: &CreateUserRequest,
+    invite: &Option<Invite>,
+) -> Result<(), ValidationError> {
+    match (&user_data.invite_code, user_data.store_id, &user_data.store_password) {
+        (&Some(_), store_id, _) => Invite::validate_invite(invite, store_id),
+        (&None, Some(store_id), Some(password)) => {
+            Store::validate_correct_store(db, store_id, password.as_ref())
+        }
+        _ => Err(ValidationError {
+            code: Cow::from("wrong_store"),
+            message: Some(Cow::from("Invite code or store credentials are required")),
+            params: HashMap::new(),
+        }),
+    }
 }
 
 impl Handler for UserCreateHandler {
@@ -56,29 +125,101 @@ impl Handler for UserCreateHandler {
         try_handler!(req.body.read_to_string(&mut payload));
         let user_data: CreateUserRequest =
             try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let remote_ip = super::client_ip(req);
         let mg = self.database.lock().unwrap();
+        let invite = match user_data.invite_code {
+            Some(ref code) => try_handler!(Invite::find_valid(&mg, code.as_ref())),
+            None => None,
+        };
+        let store_id = match invite {
+            Some(ref invite) => Some(invite.store_id),
+            None => user_data.store_id,
+        };
+        let store_key = store_id.map(|id| id.to_string());
+        let mut targets: Vec<(Scope, &str)> = vec![(Scope::Ip, remote_ip.as_ref())];
+        if let Some(ref store_key) = store_key {
+            targets.push((Scope::Store, store_key.as_ref()));
+        };
+        {
+            let rds = self.rds.lock().unwrap();
+            if let Some(retry_after) = try_handler!(locked_for(&rds, &targets)) {
+                return Ok(super::too_many_requests(retry_after));
+            };
+        }
+        let store_validation = store_credentials_validation(&mg, &user_data, &invite);
+        if store_validation.is_err() {
+            let rds = self.rds.lock().unwrap();
+            for &(scope, key) in targets.iter() {
+                try_handler!(register_failure(&rds, scope, key));
+            }
+        };
         try_validate!(
             user_data.validate(),
             vec![
-                Store::validate_correct_store(
-                    &mg,
-                    user_data.store_id,
-                    user_data.store_password.as_ref(),
-                ),
+                store_validation.clone(),
                 User::validate_unique_username(&mg, user_data.username.as_ref()),
+                User::validate_unique_email(&mg, user_data.email.as_ref()),
             ]
         );
+        let store_id = match store_id {
+            Some(store_id) => store_id,
+            None => return Ok(Response::with(status::BadRequest)),
+        };
 
-        let user: User = try_handler!(User::new(
+        if let Some(ref invite) = invite {
+            if !try_handler!(Invite::redeem(&mg, invite.uuid)) {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Invite code is invalid or expired".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::BadRequest, res)));
+            };
+        };
+        let password_hash = try_handler!(hash_password(user_data.password.as_ref()));
+        let verification = email_verification_enabled();
+        let role = invite
+            .as_ref()
+            .and_then(|invite| invite.role)
+            .unwrap_or(Role::Member);
+        let user: User = match User::new(
             &mg,
-            user_data.store_id,
+            store_id,
             user_data.username.as_ref(),
             user_data.email.as_ref(),
-            user_data.password.as_ref(),
-        ));
+            password_hash.as_ref(),
+            role,
+            !verification,
+        ) {
+            Ok(user) => user,
+            Err(e) => {
+                if let Some(ref invite) = invite {
+                    if let Err(e) = Invite::release(&mg, invite.uuid) {
+                        println!("cannot release use of invite {}: {:?}", invite.uuid, e);
+                    };
+                };
+                if let Some(violation) = User::unique_violation(&e) {
+                    return validation_error(violation);
+                };
+                try_handler!(Err::<User, postgres::Error>(e))
+            }
+        };
+        // user is already created, so failed email is only reported
+        let mut warning = None;
+        if verification {
+            let rds = self.rds.lock().unwrap();
+            let mailer = self.mailer.lock().unwrap();
+            if let Err(e) = send_verification_email(&rds, &mailer, &user) {
+                println!("cannot send verification email to user {}: {:?}", user.uuid, e);
+                warning = Some(
+                    "Verification email was not sent, request it again with resend".to_string(),
+                );
+            };
+        };
         let response = CreateUserResponse {
             success: true,
-            uuid: user.uuid.clone(),
+            uuid: user.uuid,
+            warning,
         };
         let res: String = try_handler!(serde_json::to_string(&response));
         Ok(Response::with((status::Created, res)))
@@ -90,14 +231,20 @@ impl Handler for UserCreateHandler {
 pub struct UserLoginHandler {
     database: Arc<Mutex<Connection>>,
     rds: Arc<Mutex<redis::Connection>>,
+    keys: Arc<KeyStore>,
 }
 
 impl UserLoginHandler {
     pub fn new(
         database: Arc<Mutex<Connection>>,
         rds: Arc<Mutex<redis::Connection>>,
+        keys: Arc<KeyStore>,
     ) -> UserLoginHandler {
-        UserLoginHandler { database, rds }
+        UserLoginHandler {
+            database,
+            rds,
+            keys,
+        }
     }
 }
 
@@ -105,58 +252,272 @@ impl UserLoginHandler {
 struct UserLoginRequest {
     #[validate(length(min = "2", max = "24",
                       message = "Username is not valid. Min length is 2, max - is 24"))]
-    username: String,
+    username: Option<String>,
+    #[validate(email(message = "Email is not valid"))] email: Option<String>,
     #[validate(length(min = "8", message = "Password is not valid. Min length is 8"))]
     password: String,
 }
 
+impl UserLoginRequest {
+    // User logs in either by username or by email
+    fn login(&self) -> Result<&str, ValidationError> {
+        match (&self.username, &self.email) {
+            (Some(username), &None) => Ok(username.as_ref()),
+            (&None, Some(email)) => Ok(email.as_ref()),
+            _ => Err(ValidationError {
+                code: Cow::from("login"),
+                message: Some(Cow::from("Either username or email is required")),
+                params: HashMap::new(),
+            }),
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct UserLoginResponse {
     success: bool,
     token: String,
+    refresh_token: String,
+    exp: i64,
+}
+
+// Returned instead of token pair to users with two-factor authentication
+#[derive(Serialize)]
+struct LoginChallengeResponse {
+    success: bool,
+    two_factor_required: bool,
+    challenge_token: String,
+    exp: i64,
+}
+
+fn login_challenge(
+    rds: &MutexGuard<redis::Connection>,
+    user: &User,
+) -> Result<LoginChallengeResponse, Box<dyn Error>> {
+    let challenge_token = random_token();
+    set_user_ticket(
+        rds,
+        LOGIN_CHALLENGE_TICKET,
+        challenge_token.as_ref(),
+        user.uuid,
+        user.store_id,
+        LOGIN_CHALLENGE_EXPIRATION_TIME,
+    )?;
+    Ok(LoginChallengeResponse {
+        success: true,
+        two_factor_required: true,
+        challenge_token,
+        exp: Utc::now().timestamp() + LOGIN_CHALLENGE_EXPIRATION_TIME as i64,
+    })
+}
+
+// Starts new device session and issues its first token pair
+fn start_session(
+    rds: &MutexGuard<redis::Connection>,
+    keys: &KeyStore,
+    user: &User,
+    read_only: bool,
+    user_agent: &str,
+    remote_ip: &str,
+) -> Result<UserLoginResponse, Box<dyn Error>> {
+    let session_exp = Utc::now().timestamp() + refresh_token_lifetime();
+    let (secret, device_uuid) = set_session(
+        rds,
+        user.uuid,
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        session_exp,
+        user_agent,
+        remote_ip,
+    )?;
+    issue_token_pair(rds, keys, user, read_only, secret, device_uuid)
+}
+
+// Short-lived access token plus single-use refresh token,
+// device session lives as long as its refresh token
+fn issue_token_pair(
+    rds: &MutexGuard<redis::Connection>,
+    keys: &KeyStore,
+    user: &User,
+    read_only: bool,
+    secret: String,
+    device_uuid: Uuid,
+) -> Result<UserLoginResponse, Box<dyn Error>> {
+    let now = Utc::now().timestamp();
+    let exp = now + access_token_lifetime();
+    let session_exp = now + refresh_token_lifetime();
+    extend_session(rds, user.uuid, device_uuid, session_exp)?;
+    let token = jwt::generate(
+        keys,
+        user.username.as_ref(),
+        user.uuid,
+        secret,
+        device_uuid,
+        exp,
+        user.store_id,
+        read_only,
+        user.role,
+    )?;
+    let refresh_token = set_refresh_token(
+        rds,
+        &RefreshSession {
+            uuid: user.uuid,
+            device_uuid,
+            store_id: user.store_id,
+        },
+        session_exp,
+    )?;
+    Ok(UserLoginResponse {
+        success: true,
+        token,
+        refresh_token,
+        exp,
+    })
+}
+
+// Verified users always have full access,
+// others are restricted by their store setting
+fn unverified_access(
+    db: &MutexGuard<Connection>,
+    user: &User,
+) -> Result<UnverifiedAccess, postgres::Error> {
+    match user.verified_at {
+        Some(_) => Ok(UnverifiedAccess::Full),
+        None => Store::get_unverified_access(db, user.store_id),
+    }
+}
+
+fn send_verification_email(
+    rds: &MutexGuard<redis::Connection>,
+    mailer: &MutexGuard<Box<dyn Mailer>>,
+    user: &User,
+) -> Result<(), Box<dyn Error>> {
+    let token = random_token();
+    set_user_ticket(
+        rds,
+        EMAIL_VERIFICATION_TICKET,
+        token.as_ref(),
+        user.uuid,
+        user.store_id,
+        EMAIL_VERIFICATION_EXPIRATION_TIME,
+    )?;
+    mail_verification_token(
+        mailer.as_ref(),
+        user.email.as_ref(),
+        user.username.as_ref(),
+        token.as_ref(),
+    )?;
+    Ok(())
+}
+
+fn mail_verification_token(
+    mailer: &dyn Mailer,
+    email: &str,
+    username: &str,
+    token: &str,
+) -> MailResult {
+    mailer.send(
+        email,
+        "Pizza API email verification",
+        format!(
+            "Hello, {}!\n\nUse following token to verify your email: {}\n\
+             Token is valid for {} hours.",
+            username,
+            token,
+            EMAIL_VERIFICATION_EXPIRATION_TIME / 60 / 60
+        ).as_ref(),
+    )
+}
+
+fn mail_reset_token(
+    mailer: &dyn Mailer,
+    email: &str,
+    username: &str,
+    token: &str,
+) -> MailResult {
+    mailer.send(
+        email,
+        "Pizza API password reset",
+        format!(
+            "Hello, {}!\n\nUse following token to reset your password: {}\n\
+             Token is valid for {} minutes. If you did not request password reset \
+             just ignore this email.",
+            username,
+            token,
+            PASSWORD_RESET_EXPIRATION_TIME / 60
+        ).as_ref(),
+    )
 }
 
 impl Handler for UserLoginHandler {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
         let mut payload = String::new();
         try_handler!(req.body.read_to_string(&mut payload));
+        let user_agent = match req.headers.get::<UserAgent>() {
+            Some(UserAgent(agent)) => agent.to_owned(),
+            None => String::new(),
+        };
+        let remote_ip = super::client_ip(req);
         let user_data: UserLoginRequest =
             try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
-        try_validate!(user_data.validate());
+        try_validate!(user_data.validate(), vec![user_data.login().map(|_| ())]);
+        let login = match user_data.login() {
+            Ok(login) => login,
+            Err(_) => return Ok(Response::with(status::BadRequest)),
+        };
         let mg = self.database.lock().unwrap();
         let rds = self.rds.lock().unwrap();
+        // username and email of the same user share failures counter
+        let account = match try_handler!(User::login_uuid(&mg, login, user_data.email.is_some())) {
+            Some(uuid) => uuid.to_string(),
+            None => login.to_string(),
+        };
+        let targets: [(Scope, &str); 2] = [
+            (Scope::Username, account.as_ref()),
+            (Scope::Ip, remote_ip.as_ref()),
+        ];
+        if let Some(retry_after) = try_handler!(locked_for(&rds, &targets)) {
+            return Ok(super::too_many_requests(retry_after));
+        };
 
-        let result: Option<User> = try_handler!(User::find(
-            &mg,
-            user_data.username.as_ref(),
-            user_data.password.as_ref()
-        ));
+        let result: Option<User> = if user_data.email.is_some() {
+            try_handler!(User::find_with_email(&mg, login, user_data.password.as_ref()))
+        } else {
+            try_handler!(User::find(&mg, login, user_data.password.as_ref()))
+        };
 
         if let Some(user) = result {
+            try_handler!(clear_failures(&rds, Scope::Username, account.as_ref()));
+            let read_only = match try_handler!(unverified_access(&mg, &user)) {
+                UnverifiedAccess::Denied => {
+                    let response = super::ErrorResponse {
+                        success: false,
+                        error: "Email is not verified".to_string(),
+                    };
+                    let res: String = try_handler!(serde_json::to_string(&response));
+                    return Ok(Response::with((status::Forbidden, res)));
+                }
+                access => access == UnverifiedAccess::ReadOnly,
+            };
+            if user.has_totp() {
+                let response = try_handler!(login_challenge(&rds, &user));
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::Ok, res)));
+            };
             try_handler!(User::update_login(&mg, user.uuid));
-            let exp = (Utc::now() + Duration::hours(5)).naive_utc().timestamp();
-            let (secret, device_uuid) = try_handler!(set_session(
+            let response = try_handler!(start_session(
                 &rds,
-                user.uuid,
-                Uuid::new_v4(),
-                Uuid::new_v4(),
-                exp
+                &self.keys,
+                &user,
+                read_only,
+                user_agent.as_ref(),
+                remote_ip.as_ref()
             ));
-            let token = try_handler!(jwt::generate(
-                user.username.as_ref(),
-                user.uuid,
-                secret,
-                device_uuid,
-                exp,
-                user.store_id
-            ));
-            let response = UserLoginResponse {
-                success: true,
-                token,
-            };
             let res: String = try_handler!(serde_json::to_string(&response));
             Ok(Response::with((status::Ok, res)))
         } else {
+            try_handler!(register_failure(&rds, Scope::Username, account.as_ref()));
+            try_handler!(register_failure(&rds, Scope::Ip, remote_ip.as_ref()));
             let response = super::ErrorResponse {
                 success: false,
                 error: "Wrong username or password".to_string(),
@@ -167,98 +528,1750 @@ impl Handler for UserLoginHandler {
     }
 }
 
-// Get ws token
-
-#[derive(Serialize)]
-struct GetWsTokenResponse {
-    success: bool,
-    info: String,
-    token: String,
-}
+// Login second step
 
-pub struct UserGetWsTokenHandler {
+pub struct UserLoginTwoFactorHandler {
+    database: Arc<Mutex<Connection>>,
     rds: Arc<Mutex<redis::Connection>>,
+    keys: Arc<KeyStore>,
 }
 
-impl UserGetWsTokenHandler {
-    pub fn new(rds: Arc<Mutex<redis::Connection>>) -> UserGetWsTokenHandler {
-        UserGetWsTokenHandler { rds }
+impl UserLoginTwoFactorHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        keys: Arc<KeyStore>,
+    ) -> UserLoginTwoFactorHandler {
+        UserLoginTwoFactorHandler {
+            database,
+            rds,
+            keys,
+        }
     }
 }
 
-impl Handler for UserGetWsTokenHandler {
-    fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        let store_id = try_store_id!(req.headers);
-        let user_uuid = try_user_uuid!(req.headers);
+#[derive(Deserialize)]
+struct LoginTwoFactorRequest {
+    challenge_token: String,
+    code: String,
+}
 
-        let token = random_token();
+// Accepts current TOTP code or one of unused recovery codes
+fn check_second_factor(
+    db: &MutexGuard<Connection>,
+    rds: &MutexGuard<redis::Connection>,
+    user: &User,
+    code: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let code = code.trim();
+    if let Some(secret) = user.totp_secret() {
+        if let Some(step) = verify_code(secret, code, Utc::now().timestamp()) {
+            return Ok(use_totp_step(rds, user.uuid, step)?);
+        };
+    };
+    Ok(User::use_recovery_code(
+        db,
+        user.uuid,
+        hash_recovery_code(code).as_ref(),
+    )?)
+}
+
+impl Handler for UserLoginTwoFactorHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let user_agent = match req.headers.get::<UserAgent>() {
+            Some(UserAgent(agent)) => agent.to_owned(),
+            None => String::new(),
+        };
+        let remote_ip = super::client_ip(req);
+        let challenge_data: LoginTwoFactorRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
         let rds = self.rds.lock().unwrap();
-        try_handler!(set_ws_ticket(&rds, token.clone(), user_uuid, store_id));
-        let response = GetWsTokenResponse {
-            success: true,
-            info: format!(
-                "Your ws connection token is valid for {}s",
-                WS_TICKET_EXPIRATION_TIME
-            ),
-            token,
+
+        let ticket = try_handler!(get_user_ticket(
+            &rds,
+            LOGIN_CHALLENGE_TICKET,
+            challenge_data.challenge_token.as_ref()
+        ));
+        let user = match ticket {
+            Some(ticket) => try_handler!(User::get(&mg, ticket.user_uuid, ticket.store_id)),
+            None => None,
+        };
+        let user = match user {
+            Some(user) => user,
+            None => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Challenge is invalid or expired".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::Forbidden, res)));
+            }
+        };
+
+        let account = user.uuid.to_string();
+        let targets: [(Scope, &str); 2] = [
+            (Scope::Username, account.as_ref()),
+            (Scope::Ip, remote_ip.as_ref()),
+        ];
+        if let Some(retry_after) = try_handler!(locked_for(&rds, &targets)) {
+            return Ok(super::too_many_requests(retry_after));
         };
+        if !try_handler!(check_second_factor(
+            &mg,
+            &rds,
+            &user,
+            challenge_data.code.as_ref()
+        )) {
+            try_handler!(register_failure(&rds, Scope::Username, account.as_ref()));
+            try_handler!(register_failure(&rds, Scope::Ip, remote_ip.as_ref()));
+            let response = super::ErrorResponse {
+                success: false,
+                error: "Wrong authentication code".to_string(),
+            };
+            let res: String = try_handler!(serde_json::to_string(&response));
+            return Ok(Response::with((status::Forbidden, res)));
+        };
+        try_handler!(clear_failures(&rds, Scope::Username, account.as_ref()));
+        try_handler!(take_user_ticket(
+            &rds,
+            LOGIN_CHALLENGE_TICKET,
+            challenge_data.challenge_token.as_ref()
+        ));
+
+        let read_only = match try_handler!(unverified_access(&mg, &user)) {
+            UnverifiedAccess::Denied => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Email is not verified".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::Forbidden, res)));
+            }
+            access => access == UnverifiedAccess::ReadOnly,
+        };
+        try_handler!(User::update_login(&mg, user.uuid));
+        let response = try_handler!(start_session(
+            &rds,
+            &self.keys,
+            &user,
+            read_only,
+            user_agent.as_ref(),
+            remote_ip.as_ref()
+        ));
         let res: String = try_handler!(serde_json::to_string(&response));
         Ok(Response::with((status::Ok, res)))
     }
 }
 
-// My info
+// OpenID Connect login
 
-pub struct UserInfoHandler {
+pub struct UserOidcLoginHandler {
     database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
 }
 
-impl UserInfoHandler {
-    pub fn new(database: Arc<Mutex<Connection>>) -> UserInfoHandler {
-        UserInfoHandler { database }
+impl UserOidcLoginHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserOidcLoginHandler {
+        UserOidcLoginHandler { database, rds }
     }
 }
 
 #[derive(Serialize)]
-struct UserInfoResponse {
-    username: String,
-    uuid: Uuid,
-    email: String,
-    created_at: DateTime<Utc>,
-    last_login: Option<DateTime<Utc>>,
+struct OidcLoginResponse {
+    success: bool,
+    url: String,
 }
 
-impl Handler for UserInfoHandler {
+fn oidc_not_configured() -> IronResult<Response> {
+    let response = super::ErrorResponse {
+        success: false,
+        error: "OpenID Connect login is not configured".to_string(),
+    };
+    let res: String = try_handler!(serde_json::to_string(&response));
+    Ok(Response::with((status::NotFound, res)))
+}
+
+impl Handler for UserOidcLoginHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let config = match oidc_config() {
+            Some(config) => config,
+            None => return oidc_not_configured(),
+        };
+        let store_id: i32 = {
+            let map: &Map = try_handler!(req.get_ref::<Params>());
+            match map.find(&["store_id"]) {
+                Some(Value::String(s)) => try_handler!(s.parse::<i32>(), status::BadRequest),
+                _ => {
+                    let response = super::ErrorResponse {
+                        success: false,
+                        error: "Store id is required".to_string(),
+                    };
+                    let res: String = try_handler!(serde_json::to_string(&response));
+                    return Ok(Response::with((status::BadRequest, res)));
+                }
+            }
+        };
+        {
+            let mg = self.database.lock().unwrap();
+            if !try_handler!(Store::exists(&mg, store_id)) {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Store not found".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::NotFound, res)));
+            };
+        }
+        // provider is requested without locks held
+        let metadata = try_handler!(discover(&config), status::BadGateway);
+        let state = random_token();
+        let nonce = random_token();
+        let url = try_handler!(
+            authorization_url(&config, &metadata, state.as_ref(), nonce.as_ref()),
+            status::BadGateway
+        );
+        let rds = self.rds.lock().unwrap();
+        try_handler!(set_oidc_state(
+            &rds,
+            state.as_ref(),
+            &OidcLoginState { store_id, nonce }
+        ));
+        let response = OidcLoginResponse { success: true, url };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// OpenID Connect callback
+
+pub struct UserOidcCallbackHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    keys: Arc<KeyStore>,
+}
+
+impl UserOidcCallbackHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        keys: Arc<KeyStore>,
+    ) -> UserOidcCallbackHandler {
+        UserOidcCallbackHandler {
+            database,
+            rds,
+            keys,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OidcCallbackRequest {
+    code: String,
+    state: String,
+}
+
+// Known subject logs in as its user, unknown one is bound to store user
+// with the same verified email or, if allowed, becomes new store user
+fn oidc_user(
+    db: &MutexGuard<Connection>,
+    issuer: &str,
+    claims: &IdTokenClaims,
+    store_id: i32,
+) -> Result<Option<User>, Box<dyn Error>> {
+    if let Some(user) = User::get_by_identity(db, issuer, claims.sub.as_ref())? {
+        return Ok(Some(user));
+    };
+    // account with not verified email may be registered by anyone, it is never linked
+    let existing = match claims.email {
+        Some(ref email) if claims.email_verified => User::find_by_email(db, email.as_ref())?
+            .into_iter()
+            .find(|user| user.store_id == store_id && user.verified_at.is_some()),
+        _ => None,
+    };
+    let user = match existing {
+        Some(user) => user,
+        None if oidc_auto_provision() => {
+            let username = oidc_username(db, claims)?;
+            // email owned by another account is not copied, it must stay unique
+            let email = match claims.email {
+                Some(ref email) if User::validate_unique_email(db, email.as_ref()).is_ok() => {
+                    email.as_ref()
+                }
+                _ => "",
+            };
+            let password_hash = hash_password(random_token().as_ref())?;
+            // store is chosen by client, so provisioned user is never privileged
+            User::new(
+                db,
+                store_id,
+                username.as_ref(),
+                email,
+                password_hash.as_ref(),
+                Role::Member,
+                claims.email_verified && !email.is_empty(),
+            )?
+        }
+        None => return Ok(None),
+    };
+    User::link_identity(db, user.uuid, store_id, issuer, claims.sub.as_ref())?;
+    Ok(Some(user))
+}
+
+// Username suggested by provider, suffixed when already taken
+fn oidc_username(
+    db: &MutexGuard<Connection>,
+    claims: &IdTokenClaims,
+) -> Result<String, Box<dyn Error>> {
+    let suggested = match (&claims.preferred_username, &claims.email) {
+        (Some(username), _) => username.clone(),
+        (&None, Some(email)) => email.split('@').next().unwrap_or("").to_string(),
+        _ => String::new(),
+    };
+    let mut base: String = suggested
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-' || *c == '.')
+        .take(19)
+        .collect();
+    if base.chars().count() < 2 {
+        base = "user".to_string();
+    }
+    if User::validate_unique_username(db, base.as_ref()).is_ok() {
+        return Ok(base);
+    }
+    for _ in 0..5 {
+        let username = format!("{}-{}", base, &random_token()[..4]);
+        if User::validate_unique_username(db, username.as_ref()).is_ok() {
+            return Ok(username);
+        }
+    }
+    Err(Box::new(StringError("Cannot pick unique username".to_string())))
+}
+
+impl Handler for UserOidcCallbackHandler {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        let bearer: &Authorization<Bearer> = try_handler!(
-            req.headers
-                .get::<Authorization<Bearer>>()
-                .ok_or(StringError("Server error".to_string()))
+        let config = match oidc_config() {
+            Some(config) => config,
+            None => return oidc_not_configured(),
+        };
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let user_agent = match req.headers.get::<UserAgent>() {
+            Some(UserAgent(agent)) => agent.to_owned(),
+            None => String::new(),
+        };
+        let remote_ip = super::client_ip(req);
+        let callback_data: OidcCallbackRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let login = {
+            let rds = self.rds.lock().unwrap();
+            match try_handler!(take_oidc_state(&rds, callback_data.state.as_ref())) {
+                Some(login) => login,
+                None => {
+                    let response = super::ErrorResponse {
+                        success: false,
+                        error: "Login request is invalid or expired".to_string(),
+                    };
+                    let res: String = try_handler!(serde_json::to_string(&response));
+                    return Ok(Response::with((status::BadRequest, res)));
+                }
+            }
+        };
+        let metadata = try_handler!(discover(&config), status::BadGateway);
+        let claims = try_handler!(
+            exchange_code(&config, &metadata, callback_data.code.as_ref()),
+            status::Forbidden
         );
-        let claims: Claims = try_handler!(get_claims(bearer.token.to_owned()));
+        if claims.nonce.as_ref() != Some(&login.nonce) {
+            let response = super::ErrorResponse {
+                success: false,
+                error: "Id token nonce mismatch".to_string(),
+            };
+            let res: String = try_handler!(serde_json::to_string(&response));
+            return Ok(Response::with((status::Forbidden, res)));
+        }
+
         let mg = self.database.lock().unwrap();
-        let store_id = try_store_id!(req.headers);
-        let result: Option<User> = try_handler!(User::get(&mg, claims.uuid, store_id));
-        match result {
-            Some(user) => {
-                let response = UserInfoResponse {
-                    username: user.username,
-                    uuid: user.uuid,
-                    email: user.email,
-                    created_at: user.created_at,
-                    last_login: user.last_login,
+        let rds = self.rds.lock().unwrap();
+        let user = match try_handler!(oidc_user(
+            &mg,
+            config.issuer.as_ref(),
+            &claims,
+            login.store_id
+        )) {
+            Some(ref user) if user.store_id != login.store_id => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Identity belongs to user of another store".to_string(),
                 };
                 let res: String = try_handler!(serde_json::to_string(&response));
-                Ok(Response::with((status::Ok, res)))
+                return Ok(Response::with((status::Forbidden, res)));
             }
+            Some(user) => user,
             None => {
                 let response = super::ErrorResponse {
                     success: false,
-                    error: "User not found".to_string(),
+                    error: "No user is bound to this identity".to_string(),
                 };
                 let res: String = try_handler!(serde_json::to_string(&response));
-                Ok(Response::with((status::NotFound, res)))
+                return Ok(Response::with((status::Forbidden, res)));
+            }
+        };
+        let read_only = match try_handler!(unverified_access(&mg, &user)) {
+            UnverifiedAccess::Denied => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Email is not verified".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::Forbidden, res)));
             }
+            access => access == UnverifiedAccess::ReadOnly,
+        };
+        if user.has_totp() {
+            let response = try_handler!(login_challenge(&rds, &user));
+            let res: String = try_handler!(serde_json::to_string(&response));
+            return Ok(Response::with((status::Ok, res)));
+        };
+        try_handler!(User::update_login(&mg, user.uuid));
+        let response = try_handler!(start_session(
+            &rds,
+            &self.keys,
+            &user,
+            read_only,
+            user_agent.as_ref(),
+            remote_ip.as_ref()
+        ));
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Refresh token pair
+
+pub struct UserRefreshHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    keys: Arc<KeyStore>,
+}
+
+impl UserRefreshHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        keys: Arc<KeyStore>,
+    ) -> UserRefreshHandler {
+        UserRefreshHandler {
+            database,
+            rds,
+            keys,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UserRefreshRequest {
+    refresh_token: String,
+}
+
+impl Handler for UserRefreshHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let refresh_data: UserRefreshRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+
+        let error = match try_handler!(use_refresh_token(&rds, refresh_data.refresh_token.as_ref()))
+        {
+            RefreshTokenState::Valid(session) => {
+                let secret = get_session(&rds, session.uuid, session.device_uuid).ok();
+                let user = try_handler!(User::get(&mg, session.uuid, session.store_id));
+                match (secret, user) {
+                    (Some(secret), Some(user)) => {
+                        let read_only = match try_handler!(unverified_access(&mg, &user)) {
+                            UnverifiedAccess::Denied => {
+                                try_handler!(delete_session(&rds, user.uuid, session.device_uuid));
+                                let response = super::ErrorResponse {
+                                    success: false,
+                                    error: "Email is not verified".to_string(),
+                                };
+                                let res: String = try_handler!(serde_json::to_string(&response));
+                                return Ok(Response::with((status::Forbidden, res)));
+                            }
+                            access => access == UnverifiedAccess::ReadOnly,
+                        };
+                        let response = try_handler!(issue_token_pair(
+                            &rds,
+                            &self.keys,
+                            &user,
+                            read_only,
+                            secret,
+                            session.device_uuid
+                        ));
+                        let res: String = try_handler!(serde_json::to_string(&response));
+                        return Ok(Response::with((status::Ok, res)));
+                    }
+                    _ => "Session is expired or revoked",
+                }
+            }
+            RefreshTokenState::Reused(session) => {
+                // replayed refresh token means it was stolen, so device session is dropped
+                try_handler!(delete_session(&rds, session.uuid, session.device_uuid));
+                "Refresh token was already used, session revoked"
+            }
+            RefreshTokenState::Invalid => "Wrong refresh token",
+        };
+        let response = super::ErrorResponse {
+            success: false,
+            error: error.to_string(),
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Unauthorized, res)))
+    }
+}
+
+// Verify email
+
+pub struct UserVerifyEmailHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserVerifyEmailHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserVerifyEmailHandler {
+        UserVerifyEmailHandler { database, rds }
+    }
+}
+
+#[derive(Deserialize)]
+struct VerifyEmailRequest {
+    token: String,
+}
+
+impl Handler for UserVerifyEmailHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let verify_data: VerifyEmailRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+
+        match try_handler!(take_user_ticket(
+            &rds,
+            EMAIL_VERIFICATION_TICKET,
+            verify_data.token.as_ref()
+        )) {
+            Some(ticket) => {
+                try_handler!(User::verify_email(&mg, ticket.user_uuid));
+                let response = super::SuccessResponse { success: true };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                Ok(Response::with((status::Ok, res)))
+            }
+            None => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Verification token is invalid or expired".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                Ok(Response::with((status::BadRequest, res)))
+            }
+        }
+    }
+}
+
+// Resend verification email
+
+pub struct UserVerifyEmailResendHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    mailer: Arc<Mutex<Box<dyn Mailer>>>,
+}
+
+impl UserVerifyEmailResendHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        mailer: Arc<Mutex<Box<dyn Mailer>>>,
+    ) -> UserVerifyEmailResendHandler {
+        UserVerifyEmailResendHandler {
+            database,
+            rds,
+            mailer,
+        }
+    }
+}
+
+impl Handler for UserVerifyEmailResendHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mg = self.database.lock().unwrap();
+        let error = match try_handler!(User::get(&mg, auth.uuid, auth.store_id)) {
+            Some(ref user) if user.verified_at.is_none() => {
+                let rds = self.rds.lock().unwrap();
+                let mailer = self.mailer.lock().unwrap();
+                try_handler!(send_verification_email(&rds, &mailer, user));
+                let response = super::SuccessResponse { success: true };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::Ok, res)));
+            }
+            Some(_) => "Email is already verified",
+            None => "User not found",
+        };
+        let response = super::ErrorResponse {
+            success: false,
+            error: error.to_string(),
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::BadRequest, res)))
+    }
+}
+
+// Request password reset
+
+pub struct UserResetPasswordHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    mailer: Arc<Mutex<Box<dyn Mailer>>>,
+}
+
+impl UserResetPasswordHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        mailer: Arc<Mutex<Box<dyn Mailer>>>,
+    ) -> UserResetPasswordHandler {
+        UserResetPasswordHandler {
+            database,
+            rds,
+            mailer,
+        }
+    }
+}
+
+#[derive(Validate, Deserialize)]
+struct ResetPasswordRequest {
+    #[validate(email(message = "Email is not valid"))] email: String,
+}
+
+impl Handler for UserResetPasswordHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let reset_data: ResetPasswordRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        try_validate!(reset_data.validate());
+        let remote_ip = super::client_ip(req);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+        let mailer = self.mailer.lock().unwrap();
+        let targets: [(Scope, &str); 2] = [
+            (Scope::ResetEmail, reset_data.email.as_ref()),
+            (Scope::ResetIp, remote_ip.as_ref()),
+        ];
+        if let Some(retry_after) = try_handler!(locked_for(&rds, &targets)) {
+            return Ok(super::too_many_requests(retry_after));
+        };
+        // every request counts, known email or not
+        for &(scope, id) in targets.iter() {
+            try_handler!(register_failure(&rds, scope, id));
+        }
+
+        let users: Vec<User> = try_handler!(User::find_by_email(&mg, reset_data.email.as_ref()));
+        for user in users.iter() {
+            let token = random_token();
+            try_handler!(set_user_ticket(
+                &rds,
+                PASSWORD_RESET_TICKET,
+                token.as_ref(),
+                user.uuid,
+                user.store_id,
+                PASSWORD_RESET_EXPIRATION_TIME
+            ));
+            if let Err(e) = mail_reset_token(
+                mailer.as_ref(),
+                user.email.as_ref(),
+                user.username.as_ref(),
+                token.as_ref(),
+            ) {
+                println!("cannot send password reset email to {}: {:?}", user.uuid, e);
+            };
+        }
+        // same response for unknown email and failed mail,
+        // so it cannot be used to look up accounts
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Confirm password reset
+
+pub struct UserResetPasswordConfirmHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserResetPasswordConfirmHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserResetPasswordConfirmHandler {
+        UserResetPasswordConfirmHandler { database, rds }
+    }
+}
+
+#[derive(Validate, Deserialize)]
+struct ResetPasswordConfirmRequest {
+    token: String,
+    #[validate(length(min = "8", message = "Password is not valid. Min length is 8"))]
+    password: String,
+    #[validate(must_match(other = "password", message = "Passwords do not match"))]
+    password_repeat: String,
+}
+
+impl Handler for UserResetPasswordConfirmHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let reset_data: ResetPasswordConfirmRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        try_validate!(reset_data.validate());
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+
+        match try_handler!(take_user_ticket(
+            &rds,
+            PASSWORD_RESET_TICKET,
+            reset_data.token.as_ref()
+        )) {
+            Some(ticket) => {
+                let password_hash = try_handler!(hash_password(reset_data.password.as_ref()));
+                try_handler!(User::update_password(
+                    &mg,
+                    ticket.user_uuid,
+                    password_hash.as_ref()
+                ));
+                try_handler!(delete_all_sessions(&rds, ticket.user_uuid));
+                let response = super::SuccessResponse { success: true };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                Ok(Response::with((status::Ok, res)))
+            }
+            None => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "Reset token is invalid or expired".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                Ok(Response::with((status::BadRequest, res)))
+            }
+        }
+    }
+}
+
+// Get ws token
+
+#[derive(Serialize)]
+struct GetWsTokenResponse {
+    success: bool,
+    info: String,
+    token: String,
+}
+
+pub struct UserGetWsTokenHandler {
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserGetWsTokenHandler {
+    pub fn new(rds: Arc<Mutex<redis::Connection>>) -> UserGetWsTokenHandler {
+        UserGetWsTokenHandler { rds }
+    }
+}
+
+impl Handler for UserGetWsTokenHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+
+        let token = random_token();
+        let rds = self.rds.lock().unwrap();
+        try_handler!(set_ws_ticket(
+            &rds,
+            token.clone(),
+            auth.uuid.to_string(),
+            auth.store_id
+        ));
+        let response = GetWsTokenResponse {
+            success: true,
+            info: format!(
+                "Your ws connection token is valid for {}s",
+                WS_TICKET_EXPIRATION_TIME
+            ),
+            token,
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// My info
+
+pub struct UserInfoHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl UserInfoHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> UserInfoHandler {
+        UserInfoHandler { database }
+    }
+}
+
+#[derive(Serialize)]
+struct UserInfoResponse {
+    username: String,
+    uuid: Uuid,
+    email: String,
+    role: Role,
+    created_at: DateTime<Utc>,
+    last_login: Option<DateTime<Utc>>,
+    verified_at: Option<DateTime<Utc>>,
+    two_factor_enabled: bool,
+}
+
+impl From<User> for UserInfoResponse {
+    fn from(user: User) -> UserInfoResponse {
+        UserInfoResponse {
+            two_factor_enabled: user.has_totp(),
+            username: user.username,
+            uuid: user.uuid,
+            email: user.email,
+            role: user.role,
+            created_at: user.created_at,
+            last_login: user.last_login,
+            verified_at: user.verified_at,
+        }
+    }
+}
+
+impl Handler for UserInfoHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mg = self.database.lock().unwrap();
+        let result: Option<User> = try_handler!(User::get(&mg, auth.uuid, auth.store_id));
+        match result {
+            Some(user) => {
+                let response = UserInfoResponse::from(user);
+                let res: String = try_handler!(serde_json::to_string(&response));
+                Ok(Response::with((status::Ok, res)))
+            }
+            None => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "User not found".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                Ok(Response::with((status::NotFound, res)))
+            }
+        }
+    }
+}
+
+// Update profile
+
+pub struct UserUpdateHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    mailer: Arc<Mutex<Box<dyn Mailer>>>,
+}
+
+impl UserUpdateHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        mailer: Arc<Mutex<Box<dyn Mailer>>>,
+    ) -> UserUpdateHandler {
+        UserUpdateHandler {
+            database,
+            rds,
+            mailer,
+        }
+    }
+}
+
+#[derive(Validate, Deserialize)]
+struct UpdateUserRequest {
+    #[validate(length(min = "2", max = "24",
+                      message = "Username is not valid. Min length is 2, max - is 24"))]
+    username: Option<String>,
+    #[validate(email(message = "Email is not valid"))] email: Option<String>,
+}
+
+impl Handler for UserUpdateHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let update_data: UpdateUserRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let user = match try_handler!(User::get(&mg, auth.uuid, auth.store_id)) {
+            Some(user) => user,
+            None => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "User not found".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::NotFound, res)));
+            }
+        };
+        try_validate!(
+            update_data.validate(),
+            vec![
+                // changing only letter case keeps the same name
+                match update_data.username {
+                    Some(ref username)
+                        if username.to_lowercase() != user.username.to_lowercase() =>
+                    {
+                        User::validate_unique_username(&mg, username.as_ref())
+                    }
+                    _ => Ok(()),
+                },
+                match update_data.email {
+                    Some(ref email) if email.to_lowercase() != user.email.to_lowercase() => {
+                        User::validate_unique_email(&mg, email.as_ref())
+                    }
+                    _ => Ok(()),
+                },
+            ]
+        );
+        let email_changed = match update_data.email {
+            Some(ref email) => email.to_lowercase() != user.email.to_lowercase(),
+            None => false,
+        };
+        let verification = email_verification_enabled();
+        let username = update_data.username.unwrap_or(user.username);
+        let email = update_data.email.unwrap_or(user.email);
+        if let Err(e) = User::update_profile(
+            &mg,
+            user.uuid,
+            username.as_ref(),
+            email.as_ref(),
+            verification,
+        ) {
+            if let Some(violation) = User::unique_violation(&e) {
+                return validation_error(violation);
+            };
+            try_handler!(Err::<(), postgres::Error>(e))
+        };
+        if email_changed && verification {
+            if let Some(user) = try_handler!(User::get(&mg, auth.uuid, auth.store_id)) {
+                let rds = self.rds.lock().unwrap();
+                let mailer = self.mailer.lock().unwrap();
+                // email is changed anyway, verification mail may be resent
+                if let Err(e) = send_verification_email(&rds, &mailer, &user) {
+                    println!("cannot send verification email to {}: {:?}", user.uuid, e);
+                };
+            };
+        };
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Change password
+
+pub struct UserChangePasswordHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserChangePasswordHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserChangePasswordHandler {
+        UserChangePasswordHandler { database, rds }
+    }
+}
+
+#[derive(Validate, Deserialize)]
+struct ChangePasswordRequest {
+    current_password: String,
+    #[validate(length(min = "8", message = "Password is not valid. Min length is 8"))]
+    password: String,
+    #[validate(must_match(other = "password", message = "Passwords do not match"))]
+    password_repeat: String,
+}
+
+impl Handler for UserChangePasswordHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let password_data: ChangePasswordRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+        if let Some(retry_after) = try_handler!(account_locked_for(&rds, auth.uuid)) {
+            return Ok(super::too_many_requests(retry_after));
+        };
+        let user = try_handler!(User::get(&mg, auth.uuid, auth.store_id));
+        let current_password = try_handler!(check_account_password(
+            &rds,
+            user.as_ref(),
+            auth.uuid,
+            password_data.current_password.as_ref()
+        ));
+        try_validate!(password_data.validate(), vec![current_password.clone()]);
+        let password_hash = try_handler!(hash_password(password_data.password.as_ref()));
+        try_handler!(User::update_password(&mg, auth.uuid, password_hash.as_ref()));
+        try_handler!(delete_other_sessions(&rds, auth.uuid, Some(auth.device_uuid)));
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+fn account_locked_for(
+    rds: &MutexGuard<redis::Connection>,
+    uuid: Uuid,
+) -> redis::RedisResult<Option<i64>> {
+    locked_for(rds, &[(Scope::Account, uuid.to_string().as_ref())])
+}
+
+// Checks password of logged in user, failures are throttled per account,
+// so stolen token cannot be used to guess the password
+fn check_account_password(
+    rds: &MutexGuard<redis::Connection>,
+    user: Option<&User>,
+    uuid: Uuid,
+    password: &str,
+) -> redis::RedisResult<Result<(), ValidationError>> {
+    let account = uuid.to_string();
+    match user {
+        Some(user) if user.check_password(password) => {
+            clear_failures(rds, Scope::Account, account.as_ref())?;
+            Ok(Ok(()))
+        }
+        _ => {
+            register_failure(rds, Scope::Account, account.as_ref())?;
+            Ok(Err(ValidationError {
+                code: Cow::from("wrong_password"),
+                message: Some(Cow::from("Current password is wrong")),
+                params: HashMap::new(),
+            }))
+        }
+    }
+}
+
+// Public keys of asymmetric token signing
+
+pub struct JwksHandler {
+    keys: Arc<KeyStore>,
+}
+
+impl JwksHandler {
+    pub fn new(keys: Arc<KeyStore>) -> JwksHandler {
+        JwksHandler { keys }
+    }
+}
+
+#[derive(Serialize)]
+struct JwksResponse<'a> {
+    keys: &'a Vec<Jwk>,
+}
+
+impl Handler for JwksHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let response = JwksResponse {
+            keys: self.keys.jwks(),
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Two-factor authentication enrollment
+
+pub struct UserTwoFactorEnrollHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserTwoFactorEnrollHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserTwoFactorEnrollHandler {
+        UserTwoFactorEnrollHandler { database, rds }
+    }
+}
+
+#[derive(Deserialize)]
+struct TwoFactorEnrollRequest {
+    password: String,
+}
+
+#[derive(Serialize)]
+struct TwoFactorEnrollResponse {
+    success: bool,
+    secret: String,
+    uri: String,
+}
+
+fn user_not_found() -> IronResult<Response> {
+    let response = super::ErrorResponse {
+        success: false,
+        error: "User not found".to_string(),
+    };
+    let res: String = try_handler!(serde_json::to_string(&response));
+    Ok(Response::with((status::NotFound, res)))
+}
+
+fn two_factor_error(error: &str, code: status::Status) -> IronResult<Response> {
+    let response = super::ErrorResponse {
+        success: false,
+        error: error.to_string(),
+    };
+    let res: String = try_handler!(serde_json::to_string(&response));
+    Ok(Response::with((code, res)))
+}
+
+impl Handler for UserTwoFactorEnrollHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let enroll_data: TwoFactorEnrollRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+        if let Some(retry_after) = try_handler!(account_locked_for(&rds, auth.uuid)) {
+            return Ok(super::too_many_requests(retry_after));
+        };
+        let user = match try_handler!(User::get(&mg, auth.uuid, auth.store_id)) {
+            Some(user) => user,
+            None => return user_not_found(),
+        };
+        // stolen token alone is not enough to bind attacker's authenticator
+        if try_handler!(check_account_password(
+            &rds,
+            Some(&user),
+            auth.uuid,
+            enroll_data.password.as_ref()
+        )).is_err()
+        {
+            return two_factor_error("Wrong password", status::Forbidden);
+        };
+        if user.has_totp() {
+            return two_factor_error(
+                "Two-factor authentication is already enabled",
+                status::Conflict,
+            );
+        };
+        let secret = generate_secret();
+        try_handler!(User::set_totp_secret(&mg, user.uuid, secret.as_ref()));
+        let response = TwoFactorEnrollResponse {
+            success: true,
+            uri: otpauth_uri(user.username.as_ref(), secret.as_ref()),
+            secret,
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Two-factor authentication confirmation
+
+pub struct UserTwoFactorConfirmHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserTwoFactorConfirmHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserTwoFactorConfirmHandler {
+        UserTwoFactorConfirmHandler { database, rds }
+    }
+}
+
+#[derive(Deserialize)]
+struct TwoFactorCodeRequest {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct TwoFactorConfirmResponse {
+    success: bool,
+    recovery_codes: Vec<String>,
+}
+
+impl Handler for UserTwoFactorConfirmHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let code_data: TwoFactorCodeRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+        let user = match try_handler!(User::get(&mg, auth.uuid, auth.store_id)) {
+            Some(user) => user,
+            None => return user_not_found(),
+        };
+        if user.has_totp() {
+            return two_factor_error(
+                "Two-factor authentication is already enabled",
+                status::Conflict,
+            );
+        };
+        let step = match user.totp_secret()
+            .and_then(|secret| verify_code(secret, code_data.code.trim(), Utc::now().timestamp()))
+        {
+            Some(step) => step,
+            None => return two_factor_error("Wrong authentication code", status::BadRequest),
+        };
+        try_handler!(use_totp_step(&rds, user.uuid, step));
+        let recovery_codes = generate_recovery_codes();
+        let hashes: Vec<String> = recovery_codes
+            .iter()
+            .map(|code| hash_recovery_code(code.as_ref()))
+            .collect();
+        try_handler!(User::enable_totp(&mg, user.uuid, &hashes));
+        let response = TwoFactorConfirmResponse {
+            success: true,
+            recovery_codes,
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Disable two-factor authentication
+
+pub struct UserTwoFactorDisableHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserTwoFactorDisableHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserTwoFactorDisableHandler {
+        UserTwoFactorDisableHandler { database, rds }
+    }
+}
+
+#[derive(Deserialize)]
+struct TwoFactorDisableRequest {
+    password: String,
+    code: String,
+}
+
+impl Handler for UserTwoFactorDisableHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let disable_data: TwoFactorDisableRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+        if let Some(retry_after) = try_handler!(account_locked_for(&rds, auth.uuid)) {
+            return Ok(super::too_many_requests(retry_after));
+        };
+        let user = match try_handler!(User::get(&mg, auth.uuid, auth.store_id)) {
+            Some(user) => user,
+            None => return user_not_found(),
+        };
+        if !user.has_totp() {
+            return two_factor_error(
+                "Two-factor authentication is not enabled",
+                status::BadRequest,
+            );
+        };
+        if try_handler!(check_account_password(
+            &rds,
+            Some(&user),
+            auth.uuid,
+            disable_data.password.as_ref()
+        )).is_err()
+        {
+            return two_factor_error("Wrong password", status::Forbidden);
+        };
+        if !try_handler!(check_second_factor(
+            &mg,
+            &rds,
+            &user,
+            disable_data.code.as_ref()
+        )) {
+            return two_factor_error("Wrong authentication code", status::Forbidden);
+        };
+        try_handler!(User::disable_totp(&mg, user.uuid));
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Export personal data
+
+pub struct UserExportHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserExportHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserExportHandler {
+        UserExportHandler { database, rds }
+    }
+}
+
+#[derive(Serialize)]
+struct UserExportResponse {
+    profile: UserInfoResponse,
+    sessions: Vec<SessionInfo>,
+    pizzas: Vec<Pizza>,
+}
+
+impl Handler for UserExportHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+        let user = match try_handler!(User::get(&mg, auth.uuid, auth.store_id)) {
+            Some(user) => user,
+            None => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "User not found".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::NotFound, res)));
+            }
+        };
+        let pizzas = try_handler!(Pizza::get_by_user(&mg, auth.uuid, auth.store_id));
+        let sessions = try_handler!(get_sessions(&rds, auth.uuid));
+        let response = UserExportResponse {
+            profile: UserInfoResponse::from(user),
+            sessions,
+            pizzas,
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Delete account
+
+pub struct UserDeleteHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+    ps_manager: Arc<Mutex<Manager>>,
+    s3_client: Arc<Mutex<S3Client>>,
+}
+
+impl UserDeleteHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+        ps_manager: Arc<Mutex<Manager>>,
+        s3_client: Arc<Mutex<S3Client>>,
+    ) -> UserDeleteHandler {
+        UserDeleteHandler {
+            database,
+            rds,
+            ps_manager,
+            s3_client,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteUserRequest {
+    password: String,
+}
+
+impl Handler for UserDeleteHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let delete_data: DeleteUserRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+        if let Some(retry_after) = try_handler!(account_locked_for(&rds, auth.uuid)) {
+            return Ok(super::too_many_requests(retry_after));
+        };
+        let user = try_handler!(User::get(&mg, auth.uuid, auth.store_id));
+        if try_handler!(check_account_password(
+            &rds,
+            user.as_ref(),
+            auth.uuid,
+            delete_data.password.as_ref()
+        )).is_err()
+        {
+            let response = super::ErrorResponse {
+                success: false,
+                error: "Wrong password".to_string(),
+            };
+            let res: String = try_handler!(serde_json::to_string(&response));
+            return Ok(Response::with((status::Forbidden, res)));
+        };
+        let pizzas = try_handler!(User::delete(
+            &mg,
+            auth.uuid,
+            auth.store_id,
+            account_deletion_removes_pizzas()
+        ));
+        try_handler!(delete_all_sessions(&rds, auth.uuid));
+        // removed pizzas leave queue and store clients like deleted ones
+        let mut deleted = Vec::new();
+        for pizza in pizzas.iter() {
+            try_handler!(Pizza::remove_from_queue(&rds, pizza.uuid));
+            if !pizza.deleted {
+                deleted.push((pizza.uuid.to_string(), auth.store_id));
+            };
+        }
+        if !deleted.is_empty() {
+            Pizza::emit_pizzas_event(DELETE_PIZZA_EVENT_NAME, deleted, self.ps_manager.clone());
+        };
+        // account is already gone, so leftover images are only logged
+        let s3_client = self.s3_client.lock().unwrap();
+        for pizza in pizzas.iter() {
+            let filename = pizza.img_url.trim_start_matches("static/upload/");
+            if let Err(e) = delete_object_with_filename(&s3_client, PIZZA_IMAGES_BUCKET, filename) {
+                println!("cannot delete image {} of removed pizza: {:?}", pizza.img_url, e);
+            }
+        }
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Logout
+
+pub struct UserLogoutHandler {
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserLogoutHandler {
+    pub fn new(rds: Arc<Mutex<redis::Connection>>) -> UserLogoutHandler {
+        UserLogoutHandler { rds }
+    }
+}
+
+impl Handler for UserLogoutHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let rds = self.rds.lock().unwrap();
+        try_handler!(delete_session(&rds, auth.uuid, auth.device_uuid));
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Logout from all devices
+
+pub struct UserLogoutAllHandler {
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserLogoutAllHandler {
+    pub fn new(rds: Arc<Mutex<redis::Connection>>) -> UserLogoutAllHandler {
+        UserLogoutAllHandler { rds }
+    }
+}
+
+impl Handler for UserLogoutAllHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let rds = self.rds.lock().unwrap();
+        try_handler!(delete_all_sessions(&rds, auth.uuid));
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Active sessions list
+
+pub struct UserSessionListHandler {
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserSessionListHandler {
+    pub fn new(rds: Arc<Mutex<redis::Connection>>) -> UserSessionListHandler {
+        UserSessionListHandler { rds }
+    }
+}
+
+#[derive(Serialize)]
+struct UserSessionListResponse {
+    success: bool,
+    current: Uuid,
+    results: Vec<SessionInfo>,
+}
+
+impl Handler for UserSessionListHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let rds = self.rds.lock().unwrap();
+        let sessions = try_handler!(get_sessions(&rds, auth.uuid));
+        let response = UserSessionListResponse {
+            success: true,
+            current: auth.device_uuid,
+            results: sessions,
+        };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Revoke session of single device
+
+pub struct UserSessionDeleteHandler {
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserSessionDeleteHandler {
+    pub fn new(rds: Arc<Mutex<redis::Connection>>) -> UserSessionDeleteHandler {
+        UserSessionDeleteHandler { rds }
+    }
+}
+
+impl Handler for UserSessionDeleteHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let device_uuid = try_handler!(
+            Uuid::parse_str(get_http_param!(req, "device_uuid")),
+            status::BadRequest
+        );
+        let rds = self.rds.lock().unwrap();
+        if !try_handler!(has_session(&rds, auth.uuid, device_uuid)) {
+            let response = super::ErrorResponse {
+                success: false,
+                error: "Session not found".to_string(),
+            };
+            let res: String = try_handler!(serde_json::to_string(&response));
+            return Ok(Response::with((status::NotFound, res)));
+        }
+        try_handler!(delete_session(&rds, auth.uuid, device_uuid));
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Store users list
+
+pub struct GetUserListHandler {
+    database: Arc<Mutex<Connection>>,
+}
+
+impl GetUserListHandler {
+    pub fn new(database: Arc<Mutex<Connection>>) -> GetUserListHandler {
+        GetUserListHandler { database }
+    }
+}
+
+impl Handler for GetUserListHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let mg = self.database.lock().unwrap();
+        let users: Vec<User> = try_handler!(User::get_all(&mg, store_id));
+        let response: Vec<UserInfoResponse> = users
+            .into_iter()
+            .map(UserInfoResponse::from)
+            .collect();
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+// Set user role
+
+pub struct UserSetRoleHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserSetRoleHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserSetRoleHandler {
+        UserSetRoleHandler { database, rds }
+    }
+}
+
+#[derive(Deserialize)]
+struct SetRoleRequest {
+    role: Role,
+}
+
+fn role_error(error: &str) -> IronResult<Response> {
+    let response = super::ErrorResponse {
+        success: false,
+        error: error.to_string(),
+    };
+    let res: String = try_handler!(serde_json::to_string(&response));
+    Ok(Response::with((status::Conflict, res)))
+}
+
+impl Handler for UserSetRoleHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = try_auth!(req);
+        let store_id = try_store_id!(req);
+        let uuid = try_handler!(
+            Uuid::parse_str(get_http_param!(req, "uuid")),
+            status::BadRequest
+        );
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let role_data: SetRoleRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        if uuid == auth.uuid && role_data.role != Role::Admin {
+            return role_error("Admin cannot demote themselves");
+        }
+        let mg = self.database.lock().unwrap();
+        let demotes_admin = match try_handler!(User::get(&mg, uuid, store_id)) {
+            Some(user) => user.role == Role::Admin && role_data.role != Role::Admin,
+            None => false,
+        };
+        // database connection is locked, so admins cannot be demoted concurrently
+        if demotes_admin && try_handler!(User::count_admins(&mg, store_id)) <= 1 {
+            return role_error("Store must keep at least one admin");
+        }
+        if try_handler!(User::update_role(&mg, uuid, store_id, role_data.role)) {
+            // tokens carry the role, so user has to log in again to get the new one
+            let rds = self.rds.lock().unwrap();
+            try_handler!(delete_all_sessions(&rds, uuid));
+            let response = super::SuccessResponse { success: true };
+            let res: String = try_handler!(serde_json::to_string(&response));
+            Ok(Response::with((status::Ok, res)))
+        } else {
+            let response = super::ErrorResponse {
+                success: false,
+                error: "User not found".to_string(),
+            };
+            let res: String = try_handler!(serde_json::to_string(&response));
+            Ok(Response::with((status::NotFound, res)))
+        }
+    }
+}
+
+// Clear login lockout
+
+pub struct UserClearLockoutHandler {
+    database: Arc<Mutex<Connection>>,
+    rds: Arc<Mutex<redis::Connection>>,
+}
+
+impl UserClearLockoutHandler {
+    pub fn new(
+        database: Arc<Mutex<Connection>>,
+        rds: Arc<Mutex<redis::Connection>>,
+    ) -> UserClearLockoutHandler {
+        UserClearLockoutHandler { database, rds }
+    }
+}
+
+#[derive(Deserialize)]
+struct ClearLockoutRequest {
+    username: String,
+}
+
+impl Handler for UserClearLockoutHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let store_id = try_store_id!(req);
+        let mut payload = String::new();
+        try_handler!(req.body.read_to_string(&mut payload));
+        let lockout_data: ClearLockoutRequest =
+            try_handler!(serde_json::from_str(payload.as_ref()), status::BadRequest);
+        let mg = self.database.lock().unwrap();
+        let rds = self.rds.lock().unwrap();
+
+        // admin may unlock only users of own store, counters shared
+        // with other stores (ip, store password) are left to expire
+        let user = match try_handler!(User::get_by_username(
+            &mg,
+            lockout_data.username.as_ref(),
+            store_id
+        )) {
+            Some(user) => user,
+            None => {
+                let response = super::ErrorResponse {
+                    success: false,
+                    error: "User not found".to_string(),
+                };
+                let res: String = try_handler!(serde_json::to_string(&response));
+                return Ok(Response::with((status::NotFound, res)));
+            }
+        };
+        let account = user.uuid.to_string();
+        try_handler!(clear_failures(&rds, Scope::Username, account.as_ref()));
+        try_handler!(clear_failures(&rds, Scope::Account, account.as_ref()));
+        let response = super::SuccessResponse { success: true };
+        let res: String = try_handler!(serde_json::to_string(&response));
+        Ok(Response::with((status::Ok, res)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::mailer::MemoryMailer;
+
+    #[test]
+    fn mails_verification_token() {
+        let mailer = MemoryMailer::new();
+        mail_verification_token(&mailer, "lempiy@gmail.com", "lempiy", "t0ken").unwrap();
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "lempiy@gmail.com");
+        assert_eq!(sent[0].subject, "Pizza API email verification");
+        assert!(sent[0].body.starts_with("Hello, lempiy!"));
+        assert!(sent[0].body.contains("verify your email: t0ken\n"));
+        assert!(sent[0].body.contains("valid for 24 hours"));
+    }
+
+    #[test]
+    fn mails_password_reset_token() {
+        // users of different stores may share email, each gets own token
+        let users = [("lempiy", "t0ken"), ("lempiy2", "t1ken")];
+        let mailer = MemoryMailer::new();
+        for &(username, token) in users.iter() {
+            mail_reset_token(&mailer, "lempiy@gmail.com", username, token).unwrap();
+        }
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), users.len());
+        for (mail, &(username, token)) in sent.iter().zip(users.iter()) {
+            assert_eq!(mail.to, "lempiy@gmail.com");
+            assert_eq!(mail.subject, "Pizza API password reset");
+            assert!(mail.body.starts_with(&format!("Hello, {}!", username)));
+            assert!(mail.body.contains(&format!("reset your password: {}\n", token)));
+            assert!(mail.body.contains("valid for 60 minutes"));
         }
     }
 }
@@ -1,17 +1,26 @@
+// Errors are passed up with explicit early returns across the crate
+#![allow(clippy::question_mark)]
+
 extern crate base64;
+extern crate bcrypt;
 extern crate chrono;
 extern crate env_logger;
 extern crate iron;
 extern crate iron_cors;
 extern crate jsonwebtoken as jwt;
+extern crate lettre;
+extern crate lettre_email;
 extern crate logger;
 extern crate mount;
+extern crate native_tls;
 extern crate multipart;
 extern crate num_traits;
 extern crate params;
 extern crate postgres;
 extern crate rand;
 extern crate redis;
+extern crate reqwest;
+extern crate ring;
 extern crate router;
 extern crate rusoto_core;
 extern crate rusoto_s3;
@@ -20,6 +29,7 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate untrusted;
 extern crate uuid;
 extern crate validator;
 #[macro_use]
@@ -39,7 +39,7 @@ impl Ingredient {
         offset: Option<i64>,
         limit: Option<i64>,
     ) -> Result<IngredientSet> {
-        let offset = if let Some(n) = offset { n } else { 0i64 };
+        let offset = offset.unwrap_or_default();
         let limit = if let Some(n) = limit {
             if n < DEFAULT_LIMIT {
                 n
@@ -57,7 +57,7 @@ impl Ingredient {
             Ok(query) => {
                 let count = match Ingredient::get_records_count(db) {
                     Ok(n) => n,
-                    Err(err) => return Err(Error::from(err)),
+                    Err(err) => return Err(err),
                 };
                 let mut set = IngredientSet {
                     offset,
@@ -78,20 +78,20 @@ impl Ingredient {
                 }
                 Ok(set)
             }
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
         }
     }
 
     pub fn get_records_count(db: &MutexGuard<Connection>) -> Result<i64> {
         match db.query("SELECT get_count($1);", &[&"ingredient"]) {
             Ok(query) => {
-                for row in query.iter() {
+                if let Some(row) = query.iter().next() {
                     let count = Ok(row.get(0));
                     return count;
                 }
                 Ok(0)
             }
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
         }
     }
 
@@ -99,7 +99,7 @@ impl Ingredient {
         db: &MutexGuard<Connection>,
         ingredient_ids: &Vec<i32>,
     ) -> result::Result<(), ValidationError> {
-        if ingredient_ids.len() == 0 {
+        if ingredient_ids.is_empty() {
             return Err(ValidationError {
                 code: Cow::from("wrong_ingredients"),
                 message: Some(Cow::from("Ingredients cannot be empty")),
@@ -133,10 +133,10 @@ impl Ingredient {
         query.pop();
         query += ") ORDER BY id;";
 
-        let ids: Vec<&ToSql> = ingredient_ids
+        let ids: Vec<&dyn ToSql> = ingredient_ids
             .iter()
             .map(|x| {
-                let sq: &ToSql = x;
+                let sq: &dyn ToSql = x;
                 sq
             })
             .collect();
@@ -0,0 +1,177 @@
+use uuid::Uuid;
+use chrono::DateTime;
+use chrono::offset::Utc;
+use std::sync::MutexGuard;
+use postgres::Connection;
+use postgres::rows::Row;
+use postgres::Error;
+use std::result;
+use validator::ValidationError;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use models::user::Role;
+
+// Store-scoped code letting new user join store,
+// replaces shared store password
+#[derive(Serialize)]
+pub struct Invite {
+    pub uuid: Uuid,
+    pub store_id: i32,
+    pub code: String,
+    pub role: Option<Role>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+const INVITE_COLUMNS: &str = "uuid, store_id, code, role, max_uses, uses, expires_at, \
+                                      created_by, created_at, revoked_at";
+
+type Result<T> = result::Result<T, Error>;
+
+impl Invite {
+    pub fn new(
+        db: &MutexGuard<Connection>,
+        store_id: i32,
+        code: &str,
+        role: Option<Role>,
+        max_uses: i32,
+        expires_at: DateTime<Utc>,
+        created_by: Uuid,
+    ) -> Result<Invite> {
+        let invite = Invite {
+            uuid: Uuid::new_v4(),
+            store_id,
+            code: code.to_string(),
+            role,
+            max_uses,
+            uses: 0,
+            expires_at,
+            created_by: Some(created_by),
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+        if let Err(e) = db.execute(
+            &format!(
+                "INSERT INTO store_invite ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                INVITE_COLUMNS
+            ),
+            &[
+                &invite.uuid,
+                &invite.store_id,
+                &invite.code,
+                &invite.role.map(|role| role.as_str()),
+                &invite.max_uses,
+                &invite.uses,
+                &invite.expires_at,
+                &invite.created_by,
+                &invite.created_at,
+                &invite.revoked_at,
+            ],
+        ) {
+            Err(e)
+        } else {
+            Ok(invite)
+        }
+    }
+
+    pub fn get_all(db: &MutexGuard<Connection>, store_id: i32) -> Result<Vec<Invite>> {
+        match db.query(
+            &format!(
+                "SELECT {} FROM store_invite WHERE store_id = $1 ORDER BY created_at DESC",
+                INVITE_COLUMNS
+            ),
+            &[&store_id],
+        ) {
+            Ok(query) => Ok(query.iter().map(|row| Invite::from_row(&row)).collect()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Invite which still can be used: not revoked, not expired, not exhausted
+    pub fn find_valid(db: &MutexGuard<Connection>, code: &str) -> Result<Option<Invite>> {
+        match db.query(
+            &format!(
+                "SELECT {} FROM store_invite WHERE code = $1 AND revoked_at IS NULL \
+                 AND expires_at > now() AND uses < max_uses",
+                INVITE_COLUMNS
+            ),
+            &[&code],
+        ) {
+            Ok(query) => Ok(query.iter().next().map(|row| Invite::from_row(&row))),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Takes one use of invite, false when it became invalid meanwhile
+    pub fn redeem(db: &MutexGuard<Connection>, uuid: Uuid) -> Result<bool> {
+        match db.execute(
+            "UPDATE store_invite SET uses = uses + 1 WHERE uuid = $1 AND revoked_at IS NULL \
+             AND expires_at > now() AND uses < max_uses",
+            &[&uuid],
+        ) {
+            Ok(n) => Ok(n > 0),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Gives use back when user was not created after all
+    pub fn release(db: &MutexGuard<Connection>, uuid: Uuid) -> Result<()> {
+        match db.execute(
+            "UPDATE store_invite SET uses = uses - 1 WHERE uuid = $1 AND uses > 0",
+            &[&uuid],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn revoke(db: &MutexGuard<Connection>, uuid: Uuid, store_id: i32) -> Result<bool> {
+        match db.execute(
+            "UPDATE store_invite SET revoked_at = now() \
+             WHERE uuid = $1 AND store_id = $2 AND revoked_at IS NULL",
+            &[&uuid, &store_id],
+        ) {
+            Ok(n) => Ok(n > 0),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn from_row(row: &Row) -> Invite {
+        let role: Option<String> = row.get("role");
+        Invite {
+            uuid: row.get("uuid"),
+            store_id: row.get("store_id"),
+            code: row.get("code"),
+            role: role.map(|role| Role::from_str(role.as_ref())),
+            max_uses: row.get("max_uses"),
+            uses: row.get("uses"),
+            expires_at: row.get("expires_at"),
+            created_by: row.get("created_by"),
+            created_at: row.get("created_at"),
+            revoked_at: row.get("revoked_at"),
+        }
+    }
+
+    pub fn validate_invite(
+        invite: &Option<Invite>,
+        store_id: Option<i32>,
+    ) -> result::Result<(), ValidationError> {
+        match *invite {
+            Some(ref invite) if store_id.is_none_or(|id| id == invite.store_id) => Ok(()),
+            Some(_) => Err(ValidationError {
+                code: Cow::from("wrong_invite"),
+                message: Some(Cow::from("Invite code belongs to another store")),
+                params: HashMap::new(),
+            }),
+            None => Err(ValidationError {
+                code: Cow::from("wrong_invite"),
+                message: Some(Cow::from("Invite code is invalid or expired")),
+                params: HashMap::new(),
+            }),
+        }
+    }
+}
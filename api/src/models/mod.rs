@@ -3,6 +3,7 @@ use postgres::{Connection, TlsMode};
 pub mod user;
 pub mod ingredient;
 pub mod store;
+pub mod invite;
 pub mod tag;
 pub mod pizza;
 
@@ -3,23 +3,33 @@ use chrono::DateTime;
 use chrono::offset::Utc;
 use std::sync::{Arc, Mutex, MutexGuard};
 use postgres::Connection;
+use postgres::rows::Row;
 use postgres::transaction::Transaction;
 use postgres::Error;
 use std::result;
-use utils::itob;
+use utils::{btoi, itob};
+use utils::types::StringError;
+use std::str::FromStr;
 use std::{thread, time};
-use rand::{thread_rng, Rng};
 use redis::{self, Commands};
 use postgres::types::ToSql;
 use utils::pubsub::{Manager, PubSubEvent};
-use utils::constants::{ACCEPT_PIZZA_EVENT_NAME, NOTIFICATION_THREAD_NAME};
+use utils::constants::{ACCEPT_PIZZA_EVENT_NAME, BAKE_PIZZA_EVENT_NAME, CREATE_PIZZA_EVENT_NAME,
+                       DELETE_PIZZA_EVENT_NAME, NOTIFICATION_THREAD_NAME,
+                       PICK_UP_PIZZA_EVENT_NAME, PREPARE_PIZZA_EVENT_NAME,
+                       REJECT_PIZZA_EVENT_NAME};
 use std::collections::HashMap;
 use serde_json;
 
 use super::tag::Tag;
 use super::ingredient::Ingredient;
+use super::store::Store;
+use utils::calculator::{estimate_ready_time, preparation_duration};
 
 const DEFAULT_LIMIT: i64 = 100;
+const PIZZA_QUEUE_KEY: &str = "pizza-queue";
+const LEGACY_PIZZA_QUEUE_KEY: &str = "pizza-created";
+const ACCEPT_SCHEDULER_TICK_MS: u64 = 500;
 
 pub struct CreatePizzaInput {
     pub uuid: Uuid,
@@ -35,6 +45,146 @@ pub struct CreatePizzaInput {
     pub time_prepared: DateTime<Utc>,
 }
 
+pub struct UpdatePizzaInput {
+    pub uuid: Uuid,
+    pub name: String,
+    pub store_id: i32,
+    pub price: f64,
+    pub size: i32,
+    pub description: Option<String>,
+    pub tags: Vec<i32>,
+    pub ingredients: Vec<i32>,
+    pub time_prepared: DateTime<Utc>,
+}
+
+// Lifecycle of pizza order, `accepted` and `deleted` flags follow it:
+// pizza is accepted once it is ready and deleted while it is cancelled or rejected
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PizzaStatus {
+    Created,
+    Preparing,
+    Baking,
+    Ready,
+    PickedUp,
+    Cancelled,
+    Rejected,
+}
+
+const PIZZA_STATUSES: [PizzaStatus; 7] = [
+    PizzaStatus::Created,
+    PizzaStatus::Preparing,
+    PizzaStatus::Baking,
+    PizzaStatus::Ready,
+    PizzaStatus::PickedUp,
+    PizzaStatus::Cancelled,
+    PizzaStatus::Rejected,
+];
+
+impl PizzaStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            PizzaStatus::Created => "created",
+            PizzaStatus::Preparing => "preparing",
+            PizzaStatus::Baking => "baking",
+            PizzaStatus::Ready => "ready",
+            PizzaStatus::PickedUp => "picked_up",
+            PizzaStatus::Cancelled => "cancelled",
+            PizzaStatus::Rejected => "rejected",
+        }
+    }
+
+    // Ready pizza may be skipped to when kitchen does not report
+    // intermediate steps, cancelled one only comes back with restore,
+    // kitchen rejects pizza only until it is ready
+    pub fn can_become(&self, next: PizzaStatus) -> bool {
+        match (*self, next) {
+            (PizzaStatus::Created, PizzaStatus::Preparing)
+            | (PizzaStatus::Created, PizzaStatus::Ready)
+            | (PizzaStatus::Preparing, PizzaStatus::Baking)
+            | (PizzaStatus::Preparing, PizzaStatus::Ready)
+            | (PizzaStatus::Baking, PizzaStatus::Ready)
+            | (PizzaStatus::Ready, PizzaStatus::PickedUp) => true,
+            (PizzaStatus::PickedUp, _)
+            | (PizzaStatus::Cancelled, _)
+            | (PizzaStatus::Rejected, _) => false,
+            (_, PizzaStatus::Cancelled) => true,
+            (PizzaStatus::Ready, PizzaStatus::Rejected) => false,
+            (_, PizzaStatus::Rejected) => true,
+            _ => false,
+        }
+    }
+
+    // Pizza which is not going to be made
+    pub fn is_dropped(&self) -> bool {
+        *self == PizzaStatus::Cancelled || *self == PizzaStatus::Rejected
+    }
+
+    // Statuses pizza may be moved from to given one
+    pub fn sources(next: PizzaStatus) -> Vec<PizzaStatus> {
+        PIZZA_STATUSES
+            .iter()
+            .cloned()
+            .filter(|status| status.can_become(next))
+            .collect()
+    }
+
+    fn timestamp_column(&self) -> &'static str {
+        match *self {
+            PizzaStatus::Created => "created_date",
+            PizzaStatus::Preparing => "preparing_at",
+            PizzaStatus::Baking => "baking_at",
+            PizzaStatus::Ready => "ready_at",
+            PizzaStatus::PickedUp => "picked_up_at",
+            PizzaStatus::Cancelled => "cancelled_at",
+            PizzaStatus::Rejected => "rejected_at",
+        }
+    }
+
+    pub fn event_name(&self) -> &'static str {
+        match *self {
+            PizzaStatus::Created => CREATE_PIZZA_EVENT_NAME,
+            PizzaStatus::Preparing => PREPARE_PIZZA_EVENT_NAME,
+            PizzaStatus::Baking => BAKE_PIZZA_EVENT_NAME,
+            PizzaStatus::Ready => ACCEPT_PIZZA_EVENT_NAME,
+            PizzaStatus::PickedUp => PICK_UP_PIZZA_EVENT_NAME,
+            PizzaStatus::Cancelled => DELETE_PIZZA_EVENT_NAME,
+            PizzaStatus::Rejected => REJECT_PIZZA_EVENT_NAME,
+        }
+    }
+}
+
+impl FromStr for PizzaStatus {
+    type Err = StringError;
+
+    fn from_str(s: &str) -> result::Result<PizzaStatus, StringError> {
+        match PIZZA_STATUSES.iter().find(|status| status.as_str() == s) {
+            Some(status) => Ok(*status),
+            None => Err(StringError(format!("Unknown pizza status {}", s))),
+        }
+    }
+}
+
+const PIZZA_LIST_COLUMNS: &str = "uuid, user_uuid, store_id, price, name, size, \
+                                          description, img_url, accepted, status, \
+                                          created_date, time_prepared";
+
+const PIZZA_COLUMNS: &str = "uuid, user_uuid, store_id, price, name, size, \
+                                     description, img_url, accepted, deleted, status, \
+                                     created_date, time_prepared, preparing_at, baking_at, \
+                                     ready_at, picked_up_at, cancelled_at, rejected_at, \
+                                     reject_reason";
+
+// Pizzas of deleted account are detached from it, they are shown with nil uuid of author
+fn author_uuid(row: &Row) -> Uuid {
+    let user_uuid: Option<Uuid> = row.get("user_uuid");
+    user_uuid.unwrap_or_else(Uuid::nil)
+}
+
+// Rejected pizza is deleted, but it is kept in history and
+// its author and kitchen still may get it
+const VISIBLE_PIZZA: &str = "(deleted=0 OR status='rejected')";
+
 #[derive(Serialize, Debug)]
 pub struct Pizza {
     pub uuid: Uuid,
@@ -49,8 +199,16 @@ pub struct Pizza {
     pub tags: Vec<Tag>,
     pub img_url: String,
     pub ingredients: Vec<Ingredient>,
+    pub status: PizzaStatus,
     pub created_date: DateTime<Utc>,
     pub time_prepared: DateTime<Utc>,
+    pub preparing_at: Option<DateTime<Utc>>,
+    pub baking_at: Option<DateTime<Utc>>,
+    pub ready_at: Option<DateTime<Utc>>,
+    pub picked_up_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+    pub rejected_at: Option<DateTime<Utc>>,
+    pub reject_reason: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -64,10 +222,74 @@ pub struct PizzaListOutput {
     pub price: f64,
     pub description: Option<String>,
     pub img_url: String,
+    pub status: PizzaStatus,
     pub created_date: DateTime<Utc>,
     pub time_prepared: DateTime<Utc>,
 }
 
+impl PizzaListOutput {
+    fn from_row(row: &Row) -> PizzaListOutput {
+        let status: String = row.get("status");
+        PizzaListOutput {
+            uuid: row.get("uuid"),
+            name: row.get("name"),
+            store_id: row.get("store_id"),
+            user_uuid: author_uuid(row),
+            size: row.get("size"),
+            accepted: itob(row.get("accepted")),
+            price: row.get("price"),
+            description: row.get("description"),
+            img_url: row.get("img_url"),
+            status: status.parse().unwrap_or(PizzaStatus::Created),
+            created_date: row.get("created_date"),
+            time_prepared: row.get("time_prepared"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct QueuePosition {
+    pub position: i64,
+    pub total: i64,
+    pub time_prepared: DateTime<Utc>,
+    // seconds left till pizza is ready
+    pub eta: i64,
+}
+
+// Optional conditions of store pizza history, absent ones match everything
+#[derive(Default)]
+pub struct PizzaFilter {
+    pub status: Option<PizzaStatus>,
+    pub accepted: Option<bool>,
+    pub user_uuid: Option<Uuid>,
+    pub tags: Option<Vec<i32>>,
+    pub ingredients: Option<Vec<i32>>,
+    pub size: Option<i32>,
+    pub price_from: Option<f64>,
+    pub price_to: Option<f64>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub prepared_from: Option<DateTime<Utc>>,
+    pub prepared_to: Option<DateTime<Utc>>,
+}
+
+// Sort key of history, leading "-" stands for descending order
+pub fn history_order(sort: &str) -> Option<&'static str> {
+    match sort {
+        "created_date" => Some("created_date"),
+        "-created_date" => Some("created_date DESC"),
+        "time_prepared" => Some("time_prepared"),
+        "-time_prepared" => Some("time_prepared DESC"),
+        "price" => Some("price"),
+        "-price" => Some("price DESC"),
+        "size" => Some("size"),
+        "-size" => Some("size DESC"),
+        "name" => Some("name"),
+        "-name" => Some("name DESC"),
+        _ => None,
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct PizzaSet {
     offset: i64,
@@ -77,33 +299,113 @@ pub struct PizzaSet {
 }
 
 #[derive(Serialize)]
-struct AcceptedPizzaNotification<'a> {
+struct PizzaUuidsNotification<'a> {
     store_id: i32,
-    payload: AcceptedPizzaNotificationPayload<'a>,
+    payload: PizzaUuidsNotificationPayload<'a>,
 }
 
 #[derive(Serialize)]
-struct AcceptedPizzaNotificationPayload<'a> {
+struct PizzaUuidsNotificationPayload<'a> {
     event_name: &'a str,
     data: Vec<String>,
 }
 
 type Result<T> = result::Result<T, Error>;
 
+
+impl PizzaFilter {
+    // SQL condition of visible pizzas matching filter, its values are numbered from $1
+    fn conditions(&self, store_id: i32) -> (String, Vec<Box<dyn ToSql>>) {
+        let mut conditions: Vec<String> = vec![VISIBLE_PIZZA.to_string()];
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+        {
+            let mut add = |condition: &str, value: Box<dyn ToSql>| {
+                values.push(value);
+                conditions.push(condition.replace("$N", &format!("${}", values.len())));
+            };
+            if let Some(status) = self.status {
+                add("status=$N", Box::new(status.as_str()));
+            };
+            if let Some(accepted) = self.accepted {
+                add("accepted=$N", Box::new(btoi(accepted)));
+            };
+            if let Some(user_uuid) = self.user_uuid {
+                add("user_uuid=$N", Box::new(user_uuid));
+            };
+            // pizza should have all of requested tags and ingredients
+            if let Some(ref tags) = self.tags {
+                add(
+                    &format!(
+                        "uuid IN (SELECT pizza_uuid FROM pizza_tag_{} WHERE tag_id = ANY($N) \
+                         GROUP BY pizza_uuid HAVING count(DISTINCT tag_id) = cardinality($N))",
+                        store_id
+                    ),
+                    Box::new(tags.clone()),
+                );
+            };
+            if let Some(ref ingredients) = self.ingredients {
+                add(
+                    &format!(
+                        "uuid IN (SELECT pizza_uuid FROM pizza_ingredient_{} \
+                         WHERE ingredient_id = ANY($N) GROUP BY pizza_uuid \
+                         HAVING count(DISTINCT ingredient_id) = cardinality($N))",
+                        store_id
+                    ),
+                    Box::new(ingredients.clone()),
+                );
+            };
+            if let Some(size) = self.size {
+                add("size=$N", Box::new(size));
+            };
+            if let Some(price_from) = self.price_from {
+                add("price>=$N", Box::new(price_from));
+            };
+            if let Some(price_to) = self.price_to {
+                add("price<=$N", Box::new(price_to));
+            };
+            if let Some(created_from) = self.created_from {
+                add("created_date>=$N", Box::new(created_from));
+            };
+            if let Some(created_to) = self.created_to {
+                add("created_date<=$N", Box::new(created_to));
+            };
+            if let Some(prepared_from) = self.prepared_from {
+                add("time_prepared>=$N", Box::new(prepared_from));
+            };
+            if let Some(prepared_to) = self.prepared_to {
+                add("time_prepared<=$N", Box::new(prepared_to));
+            };
+        }
+        (conditions.join(" AND "), values)
+    }
+}
+
+// Queue score of time_prepared, rounded up to milliseconds. Pizza due by score
+// of some moment is prepared by then at full precision of postgres as well
+fn queue_score(time_prepared: DateTime<Utc>) -> i64 {
+    time_prepared.timestamp() * 1000
+        + (i64::from(time_prepared.timestamp_subsec_nanos()) + 999_999) / 1_000_000
+}
+
+// Highest queue score due at given moment, rounded down to milliseconds
+fn due_score(now: DateTime<Utc>) -> i64 {
+    now.timestamp() * 1000 + i64::from(now.timestamp_subsec_nanos()) / 1_000_000
+}
+
 impl Pizza {
     pub fn create(db: &MutexGuard<Connection>, data: CreatePizzaInput) -> Result<()> {
         match db.transaction() {
             Ok(tx) => if let Err(err) = Pizza::insert_pizza(&tx, data) {
                 tx.set_rollback();
                 if let Err(e) = tx.finish() {
-                    Err(Error::from(e))
+                    Err(e)
                 } else {
-                    Err(Error::from(err))
+                    Err(err)
                 }
             } else {
                 tx.commit()
             },
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
         }
     }
 
@@ -127,48 +429,113 @@ impl Pizza {
                 &data.time_prepared,
             ],
         ) {
-            return Err(Error::from(err));
+            return Err(err);
         };
+        Pizza::insert_relations(tx, data.store_id, data.uuid, &data.tags, &data.ingredients)
+    }
+
+    fn insert_relations(
+        tx: &Transaction,
+        store_id: i32,
+        uuid: Uuid,
+        tags: &[i32],
+        ingredients: &[i32],
+    ) -> Result<()> {
         match tx.prepare(
             format!(
                 "INSERT INTO pizza_ingredient_{} (store_id, ingredient_id, pizza_uuid)\
                  VALUES ($1, $2, $3);",
-                data.store_id
+                store_id
             ).as_ref(),
         ) {
-            Ok(st) => for ingredient_id in data.ingredients.iter() {
-                if let Err(err) = st.execute(&[&data.store_id, ingredient_id, &data.uuid]) {
-                    return Err(Error::from(err));
+            Ok(st) => for ingredient_id in ingredients.iter() {
+                if let Err(err) = st.execute(&[&store_id, ingredient_id, &uuid]) {
+                    return Err(err);
                 };
             },
-            Err(err) => return Err(Error::from(err)),
+            Err(err) => return Err(err),
         };
-        if data.tags.len() > 0 {
+        if !tags.is_empty() {
             match tx.prepare(
                 format!(
                     "INSERT INTO pizza_tag_{} (store_id, tag_id, pizza_uuid)\
                      VALUES ($1, $2, $3);",
-                    data.store_id
+                    store_id
                 ).as_ref(),
             ) {
-                Ok(st) => for tag_id in data.tags.iter() {
-                    if let Err(err) = st.execute(&[&data.store_id, tag_id, &data.uuid]) {
-                        return Err(Error::from(err));
+                Ok(st) => for tag_id in tags.iter() {
+                    if let Err(err) = st.execute(&[&store_id, tag_id, &uuid]) {
+                        return Err(err);
                     };
                 },
-                Err(err) => return Err(Error::from(err)),
+                Err(err) => return Err(err),
             };
         };
         Ok(())
     }
 
+    // Changes pizza which is not accepted yet,
+    // false when it is missing, deleted or already accepted
+    pub fn update(db: &MutexGuard<Connection>, data: UpdatePizzaInput) -> Result<bool> {
+        match db.transaction() {
+            Ok(tx) => match Pizza::update_pizza(&tx, data) {
+                Ok(true) => tx.commit().map(|_| true),
+                Ok(false) => {
+                    tx.set_rollback();
+                    tx.finish().map(|_| false)
+                }
+                Err(err) => {
+                    tx.set_rollback();
+                    if let Err(e) = tx.finish() {
+                        Err(e)
+                    } else {
+                        Err(err)
+                    }
+                }
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    fn update_pizza(tx: &Transaction, data: UpdatePizzaInput) -> Result<bool> {
+        match tx.execute(
+            format!(
+                "UPDATE pizza_{} SET name=$1, size=$2, price=$3, description=$4, \
+                 time_prepared=$5 WHERE uuid=$6 AND accepted=0 AND deleted=0;",
+                data.store_id
+            ).as_ref(),
+            &[
+                &data.name,
+                &data.size,
+                &data.price,
+                &data.description,
+                &data.time_prepared,
+                &data.uuid,
+            ],
+        ) {
+            Ok(0) => return Ok(false),
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        };
+        for table in ["pizza_ingredient", "pizza_tag"].iter() {
+            if let Err(err) = tx.execute(
+                format!("DELETE FROM {}_{} WHERE pizza_uuid=$1;", table, data.store_id).as_ref(),
+                &[&data.uuid],
+            ) {
+                return Err(err);
+            };
+        }
+        Pizza::insert_relations(tx, data.store_id, data.uuid, &data.tags, &data.ingredients)
+            .map(|_| true)
+    }
+
     pub fn get_non_accepted(
         db: &MutexGuard<Connection>,
         offset: Option<i64>,
         limit: Option<i64>,
         store_id: i32,
     ) -> Result<PizzaSet> {
-        let offset = if let Some(n) = offset { n } else { 0i64 };
+        let offset = offset.unwrap_or_default();
         let limit = if let Some(n) = limit {
             if n < DEFAULT_LIMIT {
                 n
@@ -180,18 +547,16 @@ impl Pizza {
         };
         match db.query(
             format!(
-                "SELECT uuid, user_uuid, store_id, price, \
-                 name, size, description, img_url, accepted, created_date, time_prepared \
-                 FROM pizza_{} WHERE deleted=0 AND accepted=0 ORDER BY time_prepared \
-                 LIMIT $1 OFFSET $2;",
-                store_id
+                "SELECT {} FROM pizza_{} WHERE deleted=0 AND accepted=0 \
+                 ORDER BY time_prepared LIMIT $1 OFFSET $2;",
+                PIZZA_LIST_COLUMNS, store_id
             ).as_ref(),
             &[&limit, &offset],
         ) {
             Ok(query) => {
                 let count = match Pizza::get_records_count(db, store_id) {
                     Ok(n) => n,
-                    Err(err) => return Err(Error::from(err)),
+                    Err(err) => return Err(err),
                 };
                 let mut set = PizzaSet {
                     offset,
@@ -200,24 +565,71 @@ impl Pizza {
                     results: Vec::new(),
                 };
                 for row in query.iter() {
-                    let ingredient = PizzaListOutput {
-                        uuid: row.get("uuid"),
-                        name: row.get("name"),
-                        store_id: row.get("store_id"),
-                        user_uuid: row.get("user_uuid"),
-                        size: row.get("size"),
-                        accepted: itob(row.get("accepted")),
-                        price: row.get("price"),
-                        description: row.get("description"),
-                        img_url: row.get("img_url"),
-                        created_date: row.get("created_date"),
-                        time_prepared: row.get("time_prepared"),
-                    };
+                    let ingredient = PizzaListOutput::from_row(&row);
                     set.results.push(ingredient);
                 }
                 Ok(set)
             }
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Pizzas of store matching filter, count reflects filter too
+    pub fn get_history(
+        db: &MutexGuard<Connection>,
+        filter: &PizzaFilter,
+        order: &str,
+        offset: Option<i64>,
+        limit: Option<i64>,
+        store_id: i32,
+    ) -> Result<PizzaSet> {
+        let offset = offset.unwrap_or_default();
+        let limit = if let Some(n) = limit {
+            if n < DEFAULT_LIMIT {
+                n
+            } else {
+                DEFAULT_LIMIT
+            }
+        } else {
+            DEFAULT_LIMIT
+        };
+        let (condition, values) = filter.conditions(store_id);
+        let sqls: Vec<&dyn ToSql> = values.iter().map(|x| &**x).collect();
+        let count: i64 = match db.query(
+            &format!(
+                "SELECT count(*) FROM pizza_{} WHERE {};",
+                store_id, condition
+            ),
+            &sqls,
+        ) {
+            Ok(query) => query.iter().next().map(|row| row.get(0)).unwrap_or(0),
+            Err(err) => return Err(err),
+        };
+        let mut sqls = sqls;
+        sqls.push(&limit);
+        sqls.push(&offset);
+        match db.query(
+            &format!(
+                "SELECT {} FROM pizza_{} WHERE {} ORDER BY {}, uuid LIMIT ${} OFFSET ${};",
+                PIZZA_LIST_COLUMNS,
+                store_id,
+                condition,
+                order,
+                values.len() + 1,
+                values.len() + 2
+            ),
+            &sqls,
+        ) {
+            Ok(query) => Ok(PizzaSet {
+                offset,
+                limit,
+                count,
+                results: query
+                    .iter()
+                    .map(|row| PizzaListOutput::from_row(&row))
+                    .collect(),
+            }),
+            Err(err) => Err(err),
         }
     }
 
@@ -227,121 +639,204 @@ impl Pizza {
             &[&format!("pizza_{}_non_accepted", store_id)],
         ) {
             Ok(query) => {
-                for row in query.iter() {
+                if let Some(row) = query.iter().next() {
                     let count = Ok(row.get(0));
                     return count;
                 }
                 Ok(0)
             }
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
         }
     }
 
-    pub fn emulate_accept(
+    // Accepts pizzas as soon as their time_prepared comes. Queue is kept in redis
+    // sorted set scored by time_prepared in milliseconds, so it outlives restarts of the api.
+    pub fn run_accept_scheduler(
         db: Arc<Mutex<Connection>>,
         redis: Arc<Mutex<redis::Connection>>,
         mng: Arc<Mutex<Manager>>,
     ) {
-        thread::spawn(move || {
-            let mut rng = thread_rng();
-
-            loop {
-                let sleep_time_sec: u64 = rng.gen_range(30, 60);
-                let millis = time::Duration::from_millis(sleep_time_sec * 1000);
-                thread::sleep(millis);
-                let db = db.lock().unwrap();
+        {
+            let db = db.lock().unwrap();
+            let rds = redis.lock().unwrap();
+            if let Err(e) = Pizza::restore_queue(&db, &rds) {
+                println!("accept scheduler cannot restore queue, error: {:?}", e);
+            }
+        }
+        thread::spawn(move || loop {
+            thread::sleep(time::Duration::from_millis(ACCEPT_SCHEDULER_TICK_MS));
+            let now = Utc::now();
+            let due = {
                 let rds = redis.lock().unwrap();
-                let list: Vec<String> =
-                    match rds.lrange::<String, Vec<String>>("pizza-created".to_string(), 0, -1) {
-                        Ok(list) => list,
-                        Err(e) => {
-                            println!("redis get from list error: {:?}", e);
-                            panic!(e)
-                        }
-                    };
-                if list.len() > 0 {
-                    let mut hm: HashMap<String, DateTime<Utc>> = HashMap::new();
-                    let uuids: Vec<Uuid> = list.iter()
-                        .map(|x| {
-                            let v: Vec<&str> = x.split("@").collect();
-                            let time = v[1].parse::<DateTime<Utc>>().unwrap();
-                            hm.insert(v[0].to_string(), time);
-                            Uuid::parse_str(v[0]).unwrap()
-                        })
-                        .collect();
-                    let mut uuids_to_keep: Vec<String> = Vec::new();
-                    let mut uuids_to_accept: Vec<Uuid> = Vec::new();
-                    let now = Utc::now();
-                    for uid in uuids.into_iter() {
-                        let v: &DateTime<Utc> = hm.get(&uid.to_string()).unwrap();
-                        if *v > now {
-                            uuids_to_keep.push(uid.to_string() + "@" + &format!("{:?}", v))
-                        } else {
-                            uuids_to_accept.push(uid)
+                match Pizza::due_from_queue(&rds, now) {
+                    Ok(due) => due,
+                    Err(e) => {
+                        println!("accept scheduler redis error: {:?}", e);
+                        continue;
+                    }
+                }
+            };
+            if due.is_empty() {
+                continue;
+            }
+            let db = db.lock().unwrap();
+            let rds = redis.lock().unwrap();
+            let mut uuids: Vec<Uuid> = Vec::new();
+            for member in due.iter() {
+                match Uuid::parse_str(member) {
+                    Ok(uuid) => uuids.push(uuid),
+                    Err(e) => {
+                        println!("accept scheduler skips malformed entry {:?}: {:?}", member, e);
+                        if let Err(e) = rds.zrem::<&str, &str, i32>(PIZZA_QUEUE_KEY, member) {
+                            println!("accept scheduler redis error: {:?}", e);
                         }
                     }
-                    if uuids_to_accept.len() > 0 {
-                        let marks = uuids_to_accept
-                            .iter()
-                            .enumerate()
-                            .map(|x| {
-                                let (i, _) = x;
-                                format!("${}", i + 1)
-                            })
-                            .collect::<Vec<String>>()
-                            .join(",");
-                        let sqls: Vec<&ToSql> = uuids_to_accept
-                            .iter()
-                            .map(|x| {
-                                let sq: &ToSql = x;
-                                sq
-                            })
-                            .collect();
-                        let mut pizzas = Vec::new();
-                        println!("uuids_to_accept {:?}", uuids_to_accept);
-                        match db.query(
-                            format!(
-                                "UPDATE pizza \
-                                 SET accepted=1 \
-                                 WHERE uuid IN({}) \
-                                 RETURNING uuid, store_id;",
-                                marks
-                            ).as_ref(),
-                            &sqls,
-                        ) {
-                            Ok(q) => {
-                                for row in q.iter() {
-                                    let uid: Uuid = row.get("uuid");
-                                    let value: (String, i32) =
-                                        (uid.to_string(), row.get("store_id"));
-                                    pizzas.push(value);
-                                }
-                                Pizza::update_pizzas_cache(&rds, uuids_to_keep);
-                                Pizza::emit_pizzas_accepted(pizzas, mng.clone());
-                                println!("emulate_accept successful {}", sleep_time_sec);
-                            }
-                            Err(e) => println!("emulate_accept error {:?}", e),
+                }
+            }
+            if uuids.is_empty() {
+                continue;
+            }
+            match Pizza::accept_prepared(&db, &uuids, now) {
+                Ok(pizzas) => {
+                    // pizza requeued meanwhile with later time keeps its new entry,
+                    // not accepted due ones (manual mode, cancelled) are dropped
+                    for uuid in uuids.iter() {
+                        if let Err(e) = Pizza::remove_due_from_queue(&rds, *uuid, now) {
+                            println!("accept scheduler redis error: {:?}", e);
                         }
                     }
+                    if !pizzas.is_empty() {
+                        println!("accept scheduler accepted {:?}", pizzas);
+                        Pizza::emit_pizzas_accepted(pizzas, mng.clone());
+                    }
                 }
+                // entries are kept, so they are retried on next tick
+                Err(e) => println!("accept scheduler db error: {:?}", e),
             }
         });
     }
 
-    fn update_pizzas_cache(rds: &MutexGuard<redis::Connection>, keep: Vec<String>) {
-        println!("keep {:?}", keep);
-        if let Err(e) = rds.del::<String, i32>("pizza-created".to_string()) {
-            println!("cannot clear pizza-created from redis, error: {:?}", e);
-        };
-        for red_value in keep.into_iter() {
-            if let Err(e) = rds.lpush::<String, String, i32>("pizza-created".to_string(), red_value)
-            {
-                println!("Redis recreate pizza list push error: {:?}", e)
-            };
+    // Marks pizzas of stores in auto accept mode prepared by given time ready,
+    // returns uuids and stores of accepted ones
+    fn accept_prepared(
+        db: &MutexGuard<Connection>,
+        uuids: &Vec<Uuid>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<(String, i32)>> {
+        match db.query(
+            "UPDATE pizza \
+             SET accepted=1, status='ready', ready_at=now() \
+             WHERE uuid = ANY($1) AND time_prepared <= $2 \
+             AND status IN ('created', 'preparing', 'baking') \
+             AND store_id IN (SELECT id FROM store WHERE accept_mode = 'auto') \
+             RETURNING uuid, store_id;",
+            &[uuids, &now],
+        ) {
+            Ok(query) => Ok(query
+                .iter()
+                .map(|row| {
+                    let uuid: Uuid = row.get("uuid");
+                    (uuid.to_string(), row.get("store_id"))
+                })
+                .collect()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Refills queue with pizzas waiting for acceptance, in case redis lost them
+    fn restore_queue(
+        db: &MutexGuard<Connection>,
+        rds: &MutexGuard<redis::Connection>,
+    ) -> result::Result<(), StringError> {
+        Pizza::queue_waiting(db, rds, None)?;
+        // queue used to be a list of uuid@time_prepared entries
+        rds.del::<&str, i32>(LEGACY_PIZZA_QUEUE_KEY)
+            .map_err(|e| StringError(format!("{:?}", e)))?;
+        Ok(())
+    }
+
+    // Puts back to queue waiting pizzas of store which is switched to auto accept mode,
+    // ones prepared meanwhile are accepted on next check
+    pub fn requeue_store(
+        db: &MutexGuard<Connection>,
+        rds: &MutexGuard<redis::Connection>,
+        store_id: i32,
+    ) -> result::Result<(), StringError> {
+        Pizza::queue_waiting(db, rds, Some(store_id))
+    }
+
+    fn queue_waiting(
+        db: &MutexGuard<Connection>,
+        rds: &MutexGuard<redis::Connection>,
+        store_id: Option<i32>,
+    ) -> result::Result<(), StringError> {
+        let query = db.query(
+            "SELECT uuid, time_prepared FROM pizza \
+             WHERE accepted = 0 AND deleted = 0 \
+             AND status IN ('created', 'preparing', 'baking') \
+             AND ($1::int IS NULL OR store_id = $1);",
+            &[&store_id],
+        ).map_err(|e| StringError(format!("{:?}", e)))?;
+        for row in query.iter() {
+            Pizza::push_to_queue(rds, row.get("uuid"), row.get("time_prepared"))
+                .map_err(|e| StringError(format!("{:?}", e)))?;
         }
+        Ok(())
+    }
+
+    // Uuids of pizzas which time_prepared is not later than given time
+    fn due_from_queue(
+        rds: &MutexGuard<redis::Connection>,
+        now: DateTime<Utc>,
+    ) -> redis::RedisResult<Vec<String>> {
+        rds.zrangebyscore(PIZZA_QUEUE_KEY, "-inf", due_score(now))
+    }
+
+    // Adds pizza to queue of pizzas waiting for acceptance, requeued pizza gets new time
+    pub fn push_to_queue(
+        rds: &MutexGuard<redis::Connection>,
+        uuid: Uuid,
+        time_prepared: DateTime<Utc>,
+    ) -> redis::RedisResult<()> {
+        rds.zadd::<&str, i64, String, i32>(
+            PIZZA_QUEUE_KEY,
+            uuid.to_string(),
+            queue_score(time_prepared),
+        ).map(|_| ())
+    }
+
+    // Drops pizza from queue unless it was requeued with time later than given one
+    fn remove_due_from_queue(
+        rds: &MutexGuard<redis::Connection>,
+        uuid: Uuid,
+        now: DateTime<Utc>,
+    ) -> redis::RedisResult<()> {
+        let score: Option<i64> = rds.zscore(PIZZA_QUEUE_KEY, uuid.to_string())?;
+        match score {
+            Some(score) if score <= due_score(now) => Pizza::remove_from_queue(rds, uuid),
+            _ => Ok(()),
+        }
+    }
+
+    // Drops pizza from queue of pizzas waiting for acceptance
+    pub fn remove_from_queue(
+        rds: &MutexGuard<redis::Connection>,
+        uuid: Uuid,
+    ) -> redis::RedisResult<()> {
+        rds.zrem::<&str, String, i32>(PIZZA_QUEUE_KEY, uuid.to_string())
+            .map(|_| ())
     }
 
     fn emit_pizzas_accepted(pizzas: Vec<(String, i32)>, mng: Arc<Mutex<Manager>>) {
+        Pizza::emit_pizzas_event(ACCEPT_PIZZA_EVENT_NAME, pizzas, mng)
+    }
+
+    // Sends uuids of pizzas to their stores grouped by store
+    pub fn emit_pizzas_event(
+        event_name: &str,
+        pizzas: Vec<(String, i32)>,
+        mng: Arc<Mutex<Manager>>,
+    ) {
         let m = mng.lock().unwrap();
         let mut hm: HashMap<i32, Vec<String>> = HashMap::new();
         for (uuid, store_id) in pizzas.into_iter() {
@@ -352,10 +847,10 @@ impl Pizza {
             hm.insert(store_id, vec![uuid]);
         }
         for (store_id, uuids) in hm.into_iter() {
-            let event = AcceptedPizzaNotification {
+            let event = PizzaUuidsNotification {
                 store_id,
-                payload: AcceptedPizzaNotificationPayload {
-                    event_name: ACCEPT_PIZZA_EVENT_NAME,
+                payload: PizzaUuidsNotificationPayload {
+                    event_name,
                     data: uuids,
                 },
             };
@@ -375,32 +870,509 @@ impl Pizza {
     ) -> Option<PizzaListOutput> {
         match db.query(
             &format!(
-                "SELECT uuid, user_uuid, store_id, price, \
-                 name, size, description, img_url, accepted, created_date, \
-                 time_prepared from pizza_{} WHERE uuid=$1 LIMIT 1;",
-                store_id
+                "SELECT {} FROM pizza_{} WHERE uuid=$1 LIMIT 1;",
+                PIZZA_LIST_COLUMNS, store_id
             ),
             &[&uuid],
         ) {
-            Ok(query) => if query.len() > 0 {
+            Ok(query) => if !query.is_empty() {
                 let row = query.iter().last().unwrap();
-                Some(PizzaListOutput {
-                    uuid: row.get("uuid"),
-                    name: row.get("name"),
-                    store_id: row.get("store_id"),
-                    user_uuid: row.get("user_uuid"),
-                    size: row.get("size"),
-                    accepted: itob(row.get("accepted")),
-                    price: row.get("price"),
-                    description: row.get("description"),
-                    img_url: row.get("img_url"),
-                    created_date: row.get("created_date"),
-                    time_prepared: row.get("time_prepared"),
-                })
+                Some(PizzaListOutput::from_row(&row))
             } else {
                 None
             },
             _ => None,
         }
     }
+
+    // Moves pizza to given status, reason is kept when it is given.
+    // False when pizza is missing or its status does not allow that
+    pub fn set_status_with_reason(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        store_id: i32,
+        status: PizzaStatus,
+        reason: Option<&str>,
+    ) -> Result<bool> {
+        let sources: Vec<&str> = PizzaStatus::sources(status)
+            .iter()
+            .map(|source| source.as_str())
+            .collect();
+        let accepted = if status == PizzaStatus::Ready {
+            ", accepted=1"
+        } else {
+            ""
+        };
+        match db.execute(
+            &format!(
+                "UPDATE pizza_{} SET status=$1, deleted=$2, {}=now(){}, \
+                 reject_reason=COALESCE($5, reject_reason) \
+                 WHERE uuid=$3 AND status = ANY($4);",
+                store_id,
+                status.timestamp_column(),
+                accepted
+            ),
+            &[
+                &status.as_str(),
+                &btoi(status.is_dropped()),
+                &uuid,
+                &sources,
+                &reason,
+            ],
+        ) {
+            Ok(n) => Ok(n > 0),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Ready times and statuses of store pizzas waiting for acceptance, earliest first
+    pub fn get_waiting(
+        db: &MutexGuard<Connection>,
+        store_id: i32,
+        exclude: Option<Uuid>,
+    ) -> Result<Vec<(DateTime<Utc>, PizzaStatus)>> {
+        match db.query(
+            &format!(
+                "SELECT time_prepared, status FROM pizza_{} \
+                 WHERE accepted=0 AND deleted=0 AND ($1::uuid IS NULL OR uuid <> $1) \
+                 ORDER BY time_prepared;",
+                store_id
+            ),
+            &[&exclude],
+        ) {
+            Ok(query) => Ok(query
+                .iter()
+                .map(|row| {
+                    let status: String = row.get("status");
+                    (
+                        row.get("time_prepared"),
+                        status.parse().unwrap_or(PizzaStatus::Created),
+                    )
+                })
+                .collect()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Time new pizza is going to be ready at, given the store queue,
+    // pizza being changed does not wait for itself
+    pub fn estimate_time_prepared(
+        db: &MutexGuard<Connection>,
+        store_id: i32,
+        size: &i64,
+        ingredient_count: usize,
+        exclude: Option<Uuid>,
+    ) -> Result<DateTime<Utc>> {
+        let kitchen = Store::get_kitchen(db, store_id)?;
+        let waiting = Pizza::get_waiting(db, store_id, exclude)?;
+        let ready_times: Vec<DateTime<Utc>> = waiting.iter().map(|&(time, _)| time).collect();
+        let not_started = waiting
+            .iter()
+            .filter(|&&(_, status)| status == PizzaStatus::Created)
+            .count();
+        Ok(estimate_ready_time(
+            kitchen.ovens,
+            kitchen.throughput,
+            &ready_times,
+            not_started,
+            preparation_duration(size, ingredient_count),
+            Utc::now(),
+        ))
+    }
+
+    // Place of pizza among store pizzas waiting for acceptance,
+    // None when pizza is missing or does not wait anymore
+    pub fn get_queue_position(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        store_id: i32,
+    ) -> Result<Option<QueuePosition>> {
+        match db.query(
+            &format!(
+                "SELECT p.time_prepared, \
+                 (SELECT count(*) FROM pizza_{0} q WHERE q.accepted=0 AND q.deleted=0 \
+                  AND (q.time_prepared, q.created_date) < (p.time_prepared, p.created_date)) \
+                  + 1 AS position, \
+                 (SELECT count(*) FROM pizza_{0} WHERE accepted=0 AND deleted=0) AS total \
+                 FROM pizza_{0} p WHERE p.uuid=$1 AND p.accepted=0 AND p.deleted=0;",
+                store_id
+            ),
+            &[&uuid],
+        ) {
+            Ok(query) => Ok(query.iter().next().map(|row| {
+                let time_prepared: DateTime<Utc> = row.get("time_prepared");
+                let eta = time_prepared.timestamp() - Utc::now().timestamp();
+                QueuePosition {
+                    position: row.get("position"),
+                    total: row.get("total"),
+                    time_prepared,
+                    eta: if eta > 0 { eta } else { 0 },
+                }
+            })),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Brings cancelled pizza back to the latest status it has reached
+    pub fn restore(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        store_id: i32,
+    ) -> Result<Option<PizzaStatus>> {
+        match db.query(
+            &format!(
+                "UPDATE pizza_{} SET deleted=0, cancelled_at=NULL, status=CASE \
+                 WHEN picked_up_at IS NOT NULL THEN 'picked_up' \
+                 WHEN ready_at IS NOT NULL THEN 'ready' \
+                 WHEN baking_at IS NOT NULL THEN 'baking' \
+                 WHEN preparing_at IS NOT NULL THEN 'preparing' \
+                 ELSE 'created' END \
+                 WHERE uuid=$1 AND status='cancelled' RETURNING status;",
+                store_id
+            ),
+            &[&uuid],
+        ) {
+            Ok(query) => Ok(query.iter().next().and_then(|row| {
+                let status: String = row.get("status");
+                status.parse().ok()
+            })),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Single not deleted or rejected pizza with its tags and ingredients
+    pub fn get(db: &MutexGuard<Connection>, uuid: Uuid, store_id: i32) -> Result<Option<Pizza>> {
+        let query = match db.query(
+            &format!(
+                "SELECT {} FROM pizza_{} WHERE uuid=$1 AND {} LIMIT 1;",
+                PIZZA_COLUMNS, store_id, VISIBLE_PIZZA
+            ),
+            &[&uuid],
+        ) {
+            Ok(query) => query,
+            Err(err) => return Err(err),
+        };
+        let row = match query.iter().next() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let uuids = vec![uuid];
+        let mut tags = match Pizza::get_tags(db, &uuids, store_id) {
+            Ok(tags) => tags,
+            Err(err) => return Err(err),
+        };
+        let mut ingredients = match Pizza::get_ingredients(db, &uuids, store_id) {
+            Ok(ingredients) => ingredients,
+            Err(err) => return Err(err),
+        };
+        Ok(Some(Pizza::from_row(&row, &mut tags, &mut ingredients)))
+    }
+
+    // All pizzas created by user with their tags and ingredients, deleted ones included
+    pub fn get_by_user(
+        db: &MutexGuard<Connection>,
+        user_uuid: Uuid,
+        store_id: i32,
+    ) -> Result<Vec<Pizza>> {
+        let query = match db.query(
+            &format!(
+                "SELECT {} FROM pizza_{} WHERE user_uuid=$1 ORDER BY created_date;",
+                PIZZA_COLUMNS, store_id
+            ),
+            &[&user_uuid],
+        ) {
+            Ok(query) => query,
+            Err(err) => return Err(err),
+        };
+        let uuids: Vec<Uuid> = query.iter().map(|row| row.get("uuid")).collect();
+        let mut tags = match Pizza::get_tags(db, &uuids, store_id) {
+            Ok(tags) => tags,
+            Err(err) => return Err(err),
+        };
+        let mut ingredients = match Pizza::get_ingredients(db, &uuids, store_id) {
+            Ok(ingredients) => ingredients,
+            Err(err) => return Err(err),
+        };
+        Ok(query
+            .iter()
+            .map(|row| Pizza::from_row(&row, &mut tags, &mut ingredients))
+            .collect())
+    }
+
+    fn from_row(
+        row: &Row,
+        tags: &mut HashMap<Uuid, Vec<Tag>>,
+        ingredients: &mut HashMap<Uuid, Vec<Ingredient>>,
+    ) -> Pizza {
+        let uuid: Uuid = row.get("uuid");
+        let status: String = row.get("status");
+        Pizza {
+            uuid,
+            name: row.get("name"),
+            store_id: row.get("store_id"),
+            user_uuid: author_uuid(row),
+            size: row.get("size"),
+            deleted: itob(row.get("deleted")),
+            accepted: itob(row.get("accepted")),
+            price: row.get("price"),
+            description: row.get("description"),
+            tags: tags.remove(&uuid).unwrap_or_default(),
+            img_url: row.get("img_url"),
+            ingredients: ingredients.remove(&uuid).unwrap_or_default(),
+            status: status.parse().unwrap_or(PizzaStatus::Created),
+            created_date: row.get("created_date"),
+            time_prepared: row.get("time_prepared"),
+            preparing_at: row.get("preparing_at"),
+            baking_at: row.get("baking_at"),
+            ready_at: row.get("ready_at"),
+            picked_up_at: row.get("picked_up_at"),
+            cancelled_at: row.get("cancelled_at"),
+            rejected_at: row.get("rejected_at"),
+            reject_reason: row.get("reject_reason"),
+        }
+    }
+
+    fn get_tags(
+        db: &MutexGuard<Connection>,
+        uuids: &Vec<Uuid>,
+        store_id: i32,
+    ) -> Result<HashMap<Uuid, Vec<Tag>>> {
+        let mut hm: HashMap<Uuid, Vec<Tag>> = HashMap::new();
+        if uuids.is_empty() {
+            return Ok(hm);
+        };
+        match db.query(
+            &format!(
+                "SELECT pt.pizza_uuid, t.id, t.name, t.description \
+                 FROM pizza_tag_{} pt JOIN tag t ON t.id = pt.tag_id \
+                 WHERE pt.pizza_uuid = ANY($1) ORDER BY t.id;",
+                store_id
+            ),
+            &[uuids],
+        ) {
+            Ok(query) => {
+                for row in query.iter() {
+                    hm.entry(row.get("pizza_uuid")).or_default().push(Tag {
+                        id: row.get("id"),
+                        name: row.get("name"),
+                        description: row.get("description"),
+                    });
+                }
+                Ok(hm)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn get_ingredients(
+        db: &MutexGuard<Connection>,
+        uuids: &Vec<Uuid>,
+        store_id: i32,
+    ) -> Result<HashMap<Uuid, Vec<Ingredient>>> {
+        let mut hm: HashMap<Uuid, Vec<Ingredient>> = HashMap::new();
+        if uuids.is_empty() {
+            return Ok(hm);
+        };
+        match db.query(
+            &format!(
+                "SELECT pi.pizza_uuid, i.id, i.name, i.description, i.image_url, i.price, \
+                 i.created_date FROM pizza_ingredient_{} pi \
+                 JOIN ingredient i ON i.id = pi.ingredient_id \
+                 WHERE pi.pizza_uuid = ANY($1) ORDER BY i.id;",
+                store_id
+            ),
+            &[uuids],
+        ) {
+            Ok(query) => {
+                for row in query.iter() {
+                    hm.entry(row.get("pizza_uuid"))
+                        .or_default()
+                        .push(Ingredient {
+                            id: row.get("id"),
+                            name: row.get("name"),
+                            description: row.get("description"),
+                            image_url: row.get("image_url"),
+                            price: row.get("price"),
+                            created_date: row.get("created_date"),
+                        });
+                }
+                Ok(hm)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // seconds and nanoseconds
+    type Moment = (i64, u32);
+
+    // time_prepared, now and whether pizza is due by now.
+    // Pizza prepared within the same millisecond as now waits for next tick
+    const QUEUE_CASES: [(Moment, Moment, bool); 8] = [
+        ((100, 0), (100, 0), true),
+        ((100, 0), (100, 999_999), true),
+        ((99, 1_000_000), (100, 0), true),
+        ((100, 999_999_999), (101, 0), true),
+        ((100, 250_000), (100, 300_000), false),
+        ((100, 1), (100, 0), false),
+        ((100, 300_000), (100, 250_000), false),
+        ((100, 999_000_001), (100, 999_000_000), false),
+    ];
+
+    fn at(secs: i64, nanos: u32) -> DateTime<Utc> {
+        Utc.timestamp(secs, nanos)
+    }
+
+    // every legal transition, any other is refused
+    const TRANSITIONS: [(PizzaStatus, PizzaStatus); 13] = [
+        (PizzaStatus::Created, PizzaStatus::Preparing),
+        (PizzaStatus::Created, PizzaStatus::Ready),
+        (PizzaStatus::Created, PizzaStatus::Cancelled),
+        (PizzaStatus::Created, PizzaStatus::Rejected),
+        (PizzaStatus::Preparing, PizzaStatus::Baking),
+        (PizzaStatus::Preparing, PizzaStatus::Ready),
+        (PizzaStatus::Preparing, PizzaStatus::Cancelled),
+        (PizzaStatus::Preparing, PizzaStatus::Rejected),
+        (PizzaStatus::Baking, PizzaStatus::Ready),
+        (PizzaStatus::Baking, PizzaStatus::Cancelled),
+        (PizzaStatus::Baking, PizzaStatus::Rejected),
+        (PizzaStatus::Ready, PizzaStatus::PickedUp),
+        (PizzaStatus::Ready, PizzaStatus::Cancelled),
+    ];
+
+    // status, whether it may become ready and whether it may be cancelled
+    const FINISHING: [(PizzaStatus, bool, bool); 7] = [
+        (PizzaStatus::Created, true, true),
+        (PizzaStatus::Preparing, true, true),
+        (PizzaStatus::Baking, true, true),
+        (PizzaStatus::Ready, false, true),
+        (PizzaStatus::PickedUp, false, false),
+        (PizzaStatus::Cancelled, false, false),
+        (PizzaStatus::Rejected, false, false),
+    ];
+
+    #[test]
+    fn allows_only_legal_transitions() {
+        for &from in PIZZA_STATUSES.iter() {
+            for &to in PIZZA_STATUSES.iter() {
+                assert_eq!(
+                    from.can_become(to),
+                    TRANSITIONS.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn becomes_ready_or_cancelled_until_finished() {
+        for &(status, ready, cancelled) in FINISHING.iter() {
+            assert_eq!(status.can_become(PizzaStatus::Ready), ready, "{:?}", status);
+            assert_eq!(status.can_become(PizzaStatus::Cancelled), cancelled, "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn lists_sources_of_transitions() {
+        for &next in PIZZA_STATUSES.iter() {
+            let sources: Vec<PizzaStatus> = TRANSITIONS
+                .iter()
+                .filter(|&&(_, to)| to == next)
+                .map(|&(from, _)| from)
+                .collect();
+            assert_eq!(PizzaStatus::sources(next), sources, "{:?}", next);
+        }
+    }
+
+    fn full_filter() -> PizzaFilter {
+        PizzaFilter {
+            status: Some(PizzaStatus::Ready),
+            accepted: Some(true),
+            user_uuid: Some(Uuid::nil()),
+            tags: Some(vec![1, 2]),
+            ingredients: Some(vec![3]),
+            size: Some(30),
+            price_from: Some(5.0),
+            price_to: Some(10.5),
+            created_from: Some(at(100, 0)),
+            created_to: Some(at(200, 0)),
+            prepared_from: Some(at(300, 0)),
+            prepared_to: Some(at(400, 0)),
+        }
+    }
+
+    // conditions of full filter in order, values are numbered one by one
+    const FULL_CONDITIONS: [&str; 12] = [
+        "status=$1",
+        "accepted=$2",
+        "user_uuid=$3",
+        "uuid IN (SELECT pizza_uuid FROM pizza_tag_7 WHERE tag_id = ANY($4) \
+         GROUP BY pizza_uuid HAVING count(DISTINCT tag_id) = cardinality($4))",
+        "uuid IN (SELECT pizza_uuid FROM pizza_ingredient_7 WHERE ingredient_id = ANY($5) \
+         GROUP BY pizza_uuid HAVING count(DISTINCT ingredient_id) = cardinality($5))",
+        "size=$6",
+        "price>=$7",
+        "price<=$8",
+        "created_date>=$9",
+        "created_date<=$10",
+        "time_prepared>=$11",
+        "time_prepared<=$12",
+    ];
+
+    #[test]
+    fn numbers_history_filter_values() {
+        let (condition, values) = full_filter().conditions(7);
+        let mut expected = vec![VISIBLE_PIZZA];
+        expected.extend(FULL_CONDITIONS.iter());
+        assert_eq!(condition, expected.join(" AND "));
+        assert_eq!(
+            format!("{:?}", values),
+            format!(
+                "[\"ready\", 1, {:?}, [1, 2], [3], 30, 5.0, 10.5, {:?}, {:?}, {:?}, {:?}]",
+                Uuid::nil(),
+                at(100, 0),
+                at(200, 0),
+                at(300, 0),
+                at(400, 0)
+            )
+        );
+    }
+
+    #[test]
+    fn numbers_history_filter_values_from_one() {
+        let (condition, values) = PizzaFilter::default().conditions(7);
+        assert_eq!(condition, VISIBLE_PIZZA);
+        assert!(values.is_empty());
+
+        let filter = PizzaFilter {
+            size: Some(30),
+            prepared_to: Some(at(400, 0)),
+            ..Default::default()
+        };
+        let (condition, values) = filter.conditions(7);
+        assert_eq!(
+            condition,
+            format!("{} AND size=$1 AND time_prepared<=$2", VISIBLE_PIZZA)
+        );
+        assert_eq!(values.len(), 2);
+    }
+
+    #[test]
+    fn due_pizza_is_prepared_at_full_precision() {
+        for &((secs, nanos), (now_secs, now_nanos), due) in QUEUE_CASES.iter() {
+            let prepared = at(secs, nanos);
+            let now = at(now_secs, now_nanos);
+            assert_eq!(queue_score(prepared) <= due_score(now), due);
+            if due {
+                assert!(prepared <= now);
+            }
+        }
+    }
 }
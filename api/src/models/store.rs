@@ -5,6 +5,11 @@ use postgres::Error;
 use std::result;
 use std::collections::HashMap;
 use std::borrow::Cow;
+use utils::password::{hash_password, is_hashed, verify_password};
+
+// same as column defaults in schema
+const DEFAULT_OVENS: i32 = 2;
+const DEFAULT_THROUGHPUT: i32 = 30;
 
 #[derive(Serialize, Deserialize)]
 pub struct Store {
@@ -12,6 +17,58 @@ pub struct Store {
     pub name: String,
 }
 
+// Store setting which decides what users
+// with not verified email are allowed to do
+#[derive(PartialEq, Clone, Copy)]
+pub enum UnverifiedAccess {
+    Full,
+    ReadOnly,
+    Denied,
+}
+
+impl UnverifiedAccess {
+    fn from_str(s: &str) -> UnverifiedAccess {
+        match s {
+            "read_only" => UnverifiedAccess::ReadOnly,
+            "denied" => UnverifiedAccess::Denied,
+            _ => UnverifiedAccess::Full,
+        }
+    }
+}
+
+// Store setting which decides whether pizzas are accepted
+// once they are prepared or by kitchen staff
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AcceptMode {
+    Auto,
+    Manual,
+}
+
+impl AcceptMode {
+    fn from_str(s: &str) -> AcceptMode {
+        match s {
+            "manual" => AcceptMode::Manual,
+            _ => AcceptMode::Auto,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            AcceptMode::Auto => "auto",
+            AcceptMode::Manual => "manual",
+        }
+    }
+}
+
+// Kitchen capacity of store: how many pizzas are baked at once
+// and how many pizzas per hour staff prepares for baking
+#[derive(Serialize, Debug)]
+pub struct Kitchen {
+    pub ovens: i32,
+    pub throughput: i32,
+}
+
 type Result<T> = result::Result<T, Error>;
 
 impl Store {
@@ -32,29 +89,129 @@ impl Store {
                 }
                 Ok(vector)
             }
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
         }
     }
 
     pub fn find(db: &MutexGuard<Connection>, id: i32, password: &str) -> Result<Option<Store>> {
         let mut name: Option<String> = None;
+        let mut stored_password = String::new();
         match db.query(
             "SELECT id, name, password \
-             FROM store WHERE id = $1 AND password=$2",
-            &[&id, &password],
+             FROM store WHERE id = $1",
+            &[&id],
         ) {
             Ok(query) => {
-                for row in query.iter() {
+                if let Some(row) = query.iter().next() {
                     name = Some(row.get("name"));
-                    break;
+                    stored_password = row.get("password");
                 }
-                if let Some(name) = name {
-                    Ok(Some(Store { id, name }))
-                } else {
-                    Ok(None)
+                match name {
+                    Some(name) if verify_password(password, stored_password.as_ref()) => {
+                        if !is_hashed(stored_password.as_ref()) {
+                            Store::rehash_password(db, id, password);
+                        };
+                        Ok(Some(Store { id, name }))
+                    }
+                    _ => Ok(None),
                 }
             }
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Legacy store passwords are kept in plain text, they are
+    // migrated to hash upon first successful check
+    fn rehash_password(db: &MutexGuard<Connection>, id: i32, password: &str) {
+        let hashed = match hash_password(password) {
+            Ok(hashed) => hashed,
+            Err(e) => {
+                println!("cannot hash legacy password of store {}: {:?}", id, e);
+                return;
+            }
+        };
+        if let Err(e) = db.execute(
+            "UPDATE store SET password=$1 WHERE id = $2",
+            &[&hashed, &id],
+        ) {
+            println!("cannot rehash legacy password of store {}: {:?}", id, e);
+        };
+    }
+
+    pub fn exists(db: &MutexGuard<Connection>, id: i32) -> Result<bool> {
+        match db.query("SELECT EXISTS(SELECT 1 FROM store WHERE id = $1)", &[&id]) {
+            Ok(query) => Ok(query.iter().next().map(|row| row.get(0)).unwrap_or(false)),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_unverified_access(db: &MutexGuard<Connection>, id: i32) -> Result<UnverifiedAccess> {
+        match db.query(
+            "SELECT unverified_access FROM store WHERE id = $1",
+            &[&id],
+        ) {
+            Ok(query) => {
+                if let Some(row) = query.iter().next() {
+                    let access: String = row.get("unverified_access");
+                    return Ok(UnverifiedAccess::from_str(access.as_ref()));
+                }
+                Ok(UnverifiedAccess::Full)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_accept_mode(db: &MutexGuard<Connection>, id: i32) -> Result<AcceptMode> {
+        match db.query("SELECT accept_mode FROM store WHERE id = $1", &[&id]) {
+            Ok(query) => {
+                if let Some(row) = query.iter().next() {
+                    let mode: String = row.get("accept_mode");
+                    return Ok(AcceptMode::from_str(mode.as_ref()));
+                }
+                Ok(AcceptMode::Auto)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn set_accept_mode(db: &MutexGuard<Connection>, id: i32, mode: AcceptMode) -> Result<()> {
+        match db.execute(
+            "UPDATE store SET accept_mode = $1 WHERE id = $2",
+            &[&mode.as_str(), &id],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_kitchen(db: &MutexGuard<Connection>, id: i32) -> Result<Kitchen> {
+        match db.query(
+            "SELECT ovens, throughput FROM store WHERE id = $1",
+            &[&id],
+        ) {
+            Ok(query) => {
+                if let Some(row) = query.iter().next() {
+                    return Ok(Kitchen {
+                        ovens: row.get("ovens"),
+                        throughput: row.get("throughput"),
+                    });
+                }
+                Ok(Kitchen {
+                    ovens: DEFAULT_OVENS,
+                    throughput: DEFAULT_THROUGHPUT,
+                })
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn set_kitchen(db: &MutexGuard<Connection>, id: i32, kitchen: &Kitchen) -> Result<()> {
+        match db.execute(
+            "UPDATE store SET ovens = $1, throughput = $2 WHERE id = $3",
+            &[&kitchen.ovens, &kitchen.throughput, &id],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
         }
     }
 
@@ -64,7 +221,7 @@ impl Store {
         password: &str,
     ) -> result::Result<(), ValidationError> {
         match Store::find(db, id, password) {
-            Ok(result) => if let Some(_) = result {
+            Ok(result) => if result.is_some() {
                 Ok(())
             } else {
                 Err(ValidationError {
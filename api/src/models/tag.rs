@@ -33,7 +33,7 @@ impl Tag {
         offset: Option<i64>,
         limit: Option<i64>,
     ) -> Result<TagSet> {
-        let offset = if let Some(n) = offset { n } else { 0i64 };
+        let offset = offset.unwrap_or_default();
         let limit = if let Some(n) = limit {
             if n < DEFAULT_LIMIT {
                 n
@@ -51,7 +51,7 @@ impl Tag {
             Ok(query) => {
                 let count = match Tag::get_records_count(db) {
                     Ok(n) => n,
-                    Err(err) => return Err(Error::from(err)),
+                    Err(err) => return Err(err),
                 };
                 let mut set = TagSet {
                     offset,
@@ -69,20 +69,20 @@ impl Tag {
                 }
                 Ok(set)
             }
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
         }
     }
 
     pub fn get_records_count(db: &MutexGuard<Connection>) -> Result<i64> {
         match db.query("SELECT get_count($1);", &[&"tag"]) {
             Ok(query) => {
-                for row in query.iter() {
+                if let Some(row) = query.iter().next() {
                     let count = Ok(row.get(0));
                     return count;
                 }
                 Ok(0)
             }
-            Err(err) => Err(Error::from(err)),
+            Err(err) => Err(err),
         }
     }
 
@@ -90,7 +90,7 @@ impl Tag {
         db: &MutexGuard<Connection>,
         tag_ids: &Vec<i32>,
     ) -> result::Result<(), ValidationError> {
-        if tag_ids.len() == 0 {
+        if tag_ids.is_empty() {
             return Ok(());
         };
         if !has_unique_elements(tag_ids) {
@@ -109,10 +109,10 @@ impl Tag {
         );
         query.pop();
         query += ") ORDER BY id;";
-        let ids: Vec<&ToSql> = tag_ids
+        let ids: Vec<&dyn ToSql> = tag_ids
             .iter()
             .map(|x| {
-                let sq: &ToSql = x;
+                let sq: &dyn ToSql = x;
                 sq
             })
             .collect();
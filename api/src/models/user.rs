@@ -3,11 +3,16 @@ use chrono::DateTime;
 use chrono::offset::Utc;
 use std::sync::MutexGuard;
 use postgres::Connection;
+use postgres::rows::Row;
+use postgres::transaction::Transaction;
 use postgres::Error;
+use postgres::error::UNIQUE_VIOLATION;
 use std::result;
 use validator::ValidationError;
 use std::borrow::Cow;
 use std::collections::HashMap;
+use utils::password::{hash_password, is_hashed, verify_password};
+use utils::itob;
 
 #[derive(Serialize, Deserialize)]
 pub struct User {
@@ -16,10 +21,59 @@ pub struct User {
     pub username: String,
     pub email: String,
     password: String,
+    pub role: Role,
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
+    pub verified_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Member,
+    Cook,
+    Admin,
+}
+
+impl Role {
+    pub fn from_str(s: &str) -> Role {
+        match s {
+            "admin" => Role::Admin,
+            "cook" => Role::Cook,
+            _ => Role::Member,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Role::Admin => "admin",
+            Role::Cook => "cook",
+            Role::Member => "member",
+        }
+    }
+
+    // roles are ordered, admin may do everything cook can
+    pub fn allows(&self, required: Role) -> bool {
+        *self >= required
+    }
+}
+
+// Pizza removed together with its author
+pub struct RemovedPizza {
+    pub uuid: Uuid,
+    pub img_url: String,
+    // pizza was already deleted before
+    pub deleted: bool,
+}
+
+const USER_COLUMNS: &str = "uuid, store_id, username, email, password, role, \
+                                    created_at, last_login, verified_at, totp_secret, \
+                                    totp_enabled_at";
+
 type Result<T> = result::Result<T, Error>;
 
 impl User {
@@ -28,16 +82,23 @@ impl User {
         store_id: i32,
         username: &str,
         email: &str,
-        password: &str,
+        password_hash: &str,
+        role: Role,
+        verified: bool,
     ) -> Result<User> {
+        let now = Utc::now();
         let user = User {
             store_id,
             uuid: Uuid::new_v4(),
             username: username.to_string(),
             email: email.to_string(),
-            password: password.to_string(),
-            created_at: Utc::now(),
+            password: password_hash.to_string(),
+            role,
+            created_at: now,
             last_login: None,
+            verified_at: if verified { Some(now) } else { None },
+            totp_secret: None,
+            totp_enabled_at: None,
         };
 
         if let Err(e) = db.execute(
@@ -48,7 +109,9 @@ impl User {
              email, \
              password, \
              created_at, \
-             last_login\
+             last_login, \
+             verified_at, \
+             role\
              ) VALUES (\
              $1, \
              $2, \
@@ -56,7 +119,9 @@ impl User {
              $4, \
              $5, \
              $6, \
-             $7
+             $7, \
+             $8, \
+             $9
              )",
             &[
                 &user.uuid,
@@ -66,96 +131,419 @@ impl User {
                 &user.password,
                 &user.created_at,
                 &user.last_login,
+                &user.verified_at,
+                &user.role.as_str(),
             ],
         ) {
-            Err(Error::from(e))
+            Err(e)
         } else {
             Ok(user)
         }
     }
 
+    // Usernames are matched case-insensitively, legacy rows may still
+    // collide on case, so password decides which one of them logs in
     pub fn find(
         db: &MutexGuard<Connection>,
         username: &str,
         password: &str,
     ) -> Result<Option<User>> {
-        let mut uuid: Option<Uuid> = None;
-        let mut email = String::new();
-        let mut store_id = 0i32;
-        let mut created_at: Option<DateTime<Utc>> = None;
-        let mut last_login: Option<DateTime<Utc>> = None;
+        User::find_by_credentials(db, "username", username, password)
+    }
+
+    pub fn find_with_email(
+        db: &MutexGuard<Connection>,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<User>> {
+        User::find_by_credentials(db, "email", email, password)
+    }
+
+    // Account login belongs to, legacy rows colliding on case resolve to the oldest one
+    pub fn login_uuid(
+        db: &MutexGuard<Connection>,
+        login: &str,
+        email: bool,
+    ) -> Result<Option<Uuid>> {
         match db.query(
-            "SELECT uuid, store_id, email, created_at, last_login \
-             FROM person WHERE username = $1 AND password=$2",
-            &[&username, &password],
+            &format!(
+                "SELECT uuid FROM person WHERE lower({}) = lower($1) ORDER BY created_at LIMIT 1",
+                if email { "email" } else { "username" }
+            ),
+            &[&login],
+        ) {
+            Ok(query) => Ok(query.iter().next().map(|row| row.get("uuid"))),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn find_by_credentials(
+        db: &MutexGuard<Connection>,
+        column: &str,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<User>> {
+        match db.query(
+            &format!(
+                "SELECT {} FROM person WHERE lower({}) = lower($1) ORDER BY created_at",
+                USER_COLUMNS, column
+            ),
+            &[&login],
         ) {
             Ok(query) => {
                 for row in query.iter() {
-                    uuid = Some(row.get("uuid"));
-                    store_id = row.get("store_id");
-                    email = row.get("email");
-                    created_at = Some(row.get("created_at"));
-                    last_login = row.get("last_login");
-                    break;
-                }
-                if let Some(uuid) = uuid {
-                    Ok(Some(User {
-                        uuid,
-                        store_id,
-                        username: username.to_string(),
-                        email,
-                        password: username.to_string(),
-                        created_at: created_at.unwrap(),
-                        last_login,
-                    }))
-                } else {
-                    Ok(None)
+                    let mut user = User::from_row(&row);
+                    if !verify_password(password, user.password.as_ref()) {
+                        continue;
+                    };
+                    if !is_hashed(user.password.as_ref()) {
+                        if let Some(hashed) = User::rehash_password(db, user.uuid, password) {
+                            user.password = hashed;
+                        };
+                    };
+                    return Ok(Some(user));
                 }
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // Legacy rows keep password in plain text, they are
+    // migrated to hash upon first successful login
+    fn rehash_password(db: &MutexGuard<Connection>, uuid: Uuid, password: &str) -> Option<String> {
+        let hashed = match hash_password(password) {
+            Ok(hashed) => hashed,
+            Err(e) => {
+                println!("cannot hash legacy password of user {}: {:?}", uuid, e);
+                return None;
+            }
+        };
+        match User::update_password(db, uuid, hashed.as_ref()) {
+            Ok(_) => Some(hashed),
+            Err(e) => {
+                println!("cannot rehash legacy password of user {}: {:?}", uuid, e);
+                None
             }
-            Err(err) => Err(Error::from(err)),
         }
     }
 
     pub fn get(db: &MutexGuard<Connection>, uuid: Uuid, s_id: i32) -> Result<Option<User>> {
-        let mut username: Option<String> = None;
-        let mut store_id = 0i32;
-        let mut password = String::new();
-        let mut email = String::new();
-        let mut created_at: Option<DateTime<Utc>> = None;
-        let mut last_login: Option<DateTime<Utc>> = None;
         match db.query(
-            format!(
-                "SELECT username, store_id, email, password, created_at, last_login \
-                 FROM person_{} WHERE uuid = $1",
-                s_id
-            ).as_ref(),
+            &format!("SELECT {} FROM person_{} WHERE uuid = $1", USER_COLUMNS, s_id),
             &[&uuid],
         ) {
-            Ok(query) => {
-                for row in query.iter() {
-                    username = Some(row.get("username"));
-                    store_id = row.get("store_id");
-                    email = row.get("email");
-                    password = row.get("password");
-                    created_at = Some(row.get("created_at"));
-                    last_login = row.get("last_login");
-                    break;
-                }
-                if let Some(username) = username {
-                    Ok(Some(User {
-                        uuid,
-                        store_id,
-                        username,
-                        email,
-                        password,
-                        created_at: created_at.unwrap(),
-                        last_login,
-                    }))
-                } else {
-                    Ok(None)
+            Ok(query) => Ok(query.iter().next().map(|row| User::from_row(&row))),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_by_username(
+        db: &MutexGuard<Connection>,
+        username: &str,
+        store_id: i32,
+    ) -> Result<Option<User>> {
+        match db.query(
+            &format!(
+                "SELECT {} FROM person_{} WHERE lower(username) = lower($1)",
+                USER_COLUMNS, store_id
+            ),
+            &[&username],
+        ) {
+            Ok(query) => Ok(query.iter().next().map(|row| User::from_row(&row))),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_by_identity(
+        db: &MutexGuard<Connection>,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<User>> {
+        match db.query(
+            &format!(
+                "SELECT {} FROM person WHERE uuid = (\
+                 SELECT person_uuid FROM person_identity WHERE issuer = $1 AND subject = $2\
+                 )",
+                USER_COLUMNS
+            ),
+            &[&issuer, &subject],
+        ) {
+            Ok(query) => Ok(query.iter().next().map(|row| User::from_row(&row))),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Binds subject of external identity provider to user
+    pub fn link_identity(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        store_id: i32,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<()> {
+        match db.execute(
+            "INSERT INTO person_identity (issuer, subject, person_uuid, store_id, created_at) \
+             VALUES ($1, $2, $3, $4, now())",
+            &[&issuer, &subject, &uuid, &store_id],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn find_by_email(db: &MutexGuard<Connection>, email: &str) -> Result<Vec<User>> {
+        match db.query(
+            &format!(
+                "SELECT {} FROM person WHERE lower(email) = lower($1)",
+                USER_COLUMNS
+            ),
+            &[&email],
+        ) {
+            Ok(query) => Ok(query.iter().map(|row| User::from_row(&row)).collect()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_all(db: &MutexGuard<Connection>, store_id: i32) -> Result<Vec<User>> {
+        match db.query(
+            &format!(
+                "SELECT {} FROM person_{} ORDER BY created_at",
+                USER_COLUMNS, store_id
+            ),
+            &[],
+        ) {
+            Ok(query) => Ok(query.iter().map(|row| User::from_row(&row)).collect()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn count_admins(db: &MutexGuard<Connection>, store_id: i32) -> Result<i64> {
+        match db.query(
+            &format!("SELECT count(*) FROM person_{} WHERE role = $1", store_id),
+            &[&Role::Admin.as_str()],
+        ) {
+            Ok(query) => Ok(query.iter().next().map(|row| row.get(0)).unwrap_or(0)),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn from_row(row: &Row) -> User {
+        let role: String = row.get("role");
+        User {
+            uuid: row.get("uuid"),
+            store_id: row.get("store_id"),
+            username: row.get("username"),
+            email: row.get("email"),
+            password: row.get("password"),
+            role: Role::from_str(role.as_ref()),
+            created_at: row.get("created_at"),
+            last_login: row.get("last_login"),
+            verified_at: row.get("verified_at"),
+            totp_secret: row.get("totp_secret"),
+            totp_enabled_at: row.get("totp_enabled_at"),
+        }
+    }
+
+    pub fn check_password(&self, password: &str) -> bool {
+        verify_password(password, self.password.as_ref())
+    }
+
+    pub fn has_totp(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
+
+    // Secret of enabled or pending two-factor authentication
+    pub fn totp_secret(&self) -> Option<&str> {
+        self.totp_secret.as_ref().map(|secret| secret.as_ref())
+    }
+
+    // Stores secret of pending enrollment, it is used
+    // only after confirmation with first valid code
+    pub fn set_totp_secret(db: &MutexGuard<Connection>, uuid: Uuid, secret: &str) -> Result<()> {
+        match db.execute(
+            "UPDATE person SET totp_secret=$1, totp_enabled_at=NULL WHERE uuid = $2",
+            &[&secret, &uuid],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn enable_totp(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        recovery_code_hashes: &Vec<String>,
+    ) -> Result<()> {
+        match db.execute(
+            "UPDATE person SET totp_enabled_at=now(), recovery_codes=$1 WHERE uuid = $2",
+            &[recovery_code_hashes, &uuid],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn disable_totp(db: &MutexGuard<Connection>, uuid: Uuid) -> Result<()> {
+        match db.execute(
+            "UPDATE person SET totp_secret=NULL, totp_enabled_at=NULL, recovery_codes='{}' \
+             WHERE uuid = $1",
+            &[&uuid],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Recovery code is removed once used, returns false for unknown code
+    pub fn use_recovery_code(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        code_hash: &str,
+    ) -> Result<bool> {
+        match db.execute(
+            "UPDATE person SET recovery_codes=array_remove(recovery_codes, $1) \
+             WHERE uuid = $2 AND $1 = ANY(recovery_codes)",
+            &[&code_hash, &uuid],
+        ) {
+            Ok(n) => Ok(n > 0),
+            Err(err) => Err(err),
+        }
+    }
+
+    // New email has to be verified again when reverify is set
+    pub fn update_profile(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        username: &str,
+        email: &str,
+        reverify: bool,
+    ) -> Result<()> {
+        match db.execute(
+            "UPDATE person SET username=$1, email=$2, verified_at=CASE \
+             WHEN $4 AND lower(email) <> lower($2) THEN NULL ELSE verified_at END \
+             WHERE uuid = $3",
+            &[&username, &email, &uuid, &reverify],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Removes user together with own pizzas or detaches pizzas from user,
+    // returns removed pizzas
+    pub fn delete(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        store_id: i32,
+        remove_pizzas: bool,
+    ) -> Result<Vec<RemovedPizza>> {
+        match db.transaction() {
+            Ok(tx) => match User::delete_with_pizzas(&tx, uuid, store_id, remove_pizzas) {
+                Ok(pizzas) => match tx.commit() {
+                    Ok(_) => Ok(pizzas),
+                    Err(err) => Err(err),
+                },
+                Err(err) => {
+                    tx.set_rollback();
+                    if let Err(e) = tx.finish() {
+                        Err(e)
+                    } else {
+                        Err(err)
+                    }
                 }
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    fn delete_with_pizzas(
+        tx: &Transaction,
+        uuid: Uuid,
+        store_id: i32,
+        remove_pizzas: bool,
+    ) -> Result<Vec<RemovedPizza>> {
+        let pizzas: Vec<RemovedPizza> = if remove_pizzas {
+            match tx.query(
+                &format!(
+                    "DELETE FROM pizza_{} WHERE user_uuid = $1 RETURNING uuid, img_url, deleted",
+                    store_id
+                ),
+                &[&uuid],
+            ) {
+                Ok(query) => query
+                    .iter()
+                    .map(|row| RemovedPizza {
+                        uuid: row.get("uuid"),
+                        img_url: row.get("img_url"),
+                        deleted: itob(row.get("deleted")),
+                    })
+                    .collect(),
+                Err(err) => return Err(err),
             }
-            Err(err) => Err(Error::from(err)),
+        } else {
+            if let Err(err) = tx.execute(
+                &format!(
+                    "UPDATE pizza_{} SET user_uuid = NULL WHERE user_uuid = $1",
+                    store_id
+                ),
+                &[&uuid],
+            ) {
+                return Err(err);
+            };
+            Vec::new()
+        };
+        if let Err(err) = tx.execute(
+            "DELETE FROM person_identity WHERE person_uuid = $1",
+            &[&uuid],
+        ) {
+            return Err(err);
+        };
+        match tx.execute(
+            &format!("DELETE FROM person_{} WHERE uuid = $1", store_id),
+            &[&uuid],
+        ) {
+            Ok(_) => Ok(pizzas),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn update_role(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        store_id: i32,
+        role: Role,
+    ) -> Result<bool> {
+        match db.execute(
+            &format!("UPDATE person_{} SET role=$1 WHERE uuid = $2", store_id),
+            &[&role.as_str(), &uuid],
+        ) {
+            Ok(n) => Ok(n > 0),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn update_password(
+        db: &MutexGuard<Connection>,
+        uuid: Uuid,
+        password_hash: &str,
+    ) -> Result<()> {
+        match db.execute(
+            "UPDATE person SET password=$1 WHERE uuid = $2",
+            &[&password_hash, &uuid],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn verify_email(db: &MutexGuard<Connection>, uuid: Uuid) -> Result<()> {
+        match db.execute(
+            "UPDATE person SET verified_at=now() WHERE uuid = $1 AND verified_at IS NULL",
+            &[&uuid],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
         }
     }
 
@@ -169,27 +557,47 @@ impl User {
         }
     }
 
+    // Concurrent requests may both pass uniqueness validation,
+    // unique indexes reject the latter, it is reported like validation error
+    pub fn unique_violation(err: &Error) -> Option<ValidationError> {
+        if err.code() != Some(&UNIQUE_VIOLATION) {
+            return None;
+        };
+        let constraint = err.as_db()
+            .and_then(|db_err| db_err.constraint.clone())
+            .unwrap_or_default();
+        if constraint.contains("email") {
+            Some(ValidationError {
+                code: Cow::from("duplicate_email"),
+                message: Some(Cow::from("User with such email already exists")),
+                params: HashMap::new(),
+            })
+        } else {
+            Some(ValidationError {
+                code: Cow::from("duplicate_username"),
+                message: Some(Cow::from("User with such username already exists")),
+                params: HashMap::new(),
+            })
+        }
+    }
+
     pub fn validate_unique_username(
         db: &MutexGuard<Connection>,
         username: &str,
     ) -> result::Result<(), ValidationError> {
-        let mut uuid: Option<Uuid> = None;
-        match db.query("SELECT uuid FROM person WHERE username = $1", &[&username]) {
-            Ok(query) => {
-                for row in query.iter() {
-                    uuid = Some(row.get("uuid"));
-                    break;
-                }
-                if let Some(_) = uuid {
-                    Err(ValidationError {
-                        code: Cow::from("duplicate_username"),
-                        message: Some(Cow::from("User with such username already exists")),
-                        params: HashMap::new(),
-                    })
-                } else {
-                    Ok(())
-                }
-            }
+        match db.query(
+            "SELECT uuid FROM person WHERE lower(username) = lower($1) LIMIT 1",
+            &[&username],
+        ) {
+            Ok(query) => if query.is_empty() {
+                Ok(())
+            } else {
+                Err(ValidationError {
+                    code: Cow::from("duplicate_username"),
+                    message: Some(Cow::from("User with such username already exists")),
+                    params: HashMap::new(),
+                })
+            },
             Err(_) => Err(ValidationError {
                 code: Cow::from("duplicate_username"),
                 message: Some(Cow::from("Cannot check username uniqueness")),
@@ -197,4 +605,29 @@ impl User {
             }),
         }
     }
+
+    pub fn validate_unique_email(
+        db: &MutexGuard<Connection>,
+        email: &str,
+    ) -> result::Result<(), ValidationError> {
+        match db.query(
+            "SELECT uuid FROM person WHERE lower(email) = lower($1) LIMIT 1",
+            &[&email],
+        ) {
+            Ok(query) => if query.is_empty() {
+                Ok(())
+            } else {
+                Err(ValidationError {
+                    code: Cow::from("duplicate_email"),
+                    message: Some(Cow::from("User with such email already exists")),
+                    params: HashMap::new(),
+                })
+            },
+            Err(_) => Err(ValidationError {
+                code: Cow::from("duplicate_email"),
+                message: Some(Cow::from("Cannot check email uniqueness")),
+                params: HashMap::new(),
+            }),
+        }
+    }
 }
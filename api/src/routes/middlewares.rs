@@ -3,9 +3,12 @@ use iron::headers::{Authorization, Bearer, ContentType};
 use iron::{AfterMiddleware, BeforeMiddleware};
 use std::sync::{Arc, Mutex};
 use utils::types::StringError;
-use utils::jwt::check;
+use utils::jwt::{check, AuthContext};
+use utils::jwk::KeyStore;
+use utils::cache::touch_session;
 use iron::modifiers;
 use redis::Connection;
+use models::user::Role;
 
 pub struct JsonAfterMiddleware;
 
@@ -18,24 +21,33 @@ impl AfterMiddleware for JsonAfterMiddleware {
 
 pub struct AuthBeforeMiddleware {
     rds: Arc<Mutex<Connection>>,
+    keys: Arc<KeyStore>,
 }
 
 impl AuthBeforeMiddleware {
-    pub fn new(rds: Arc<Mutex<Connection>>) -> AuthBeforeMiddleware {
-        AuthBeforeMiddleware { rds }
+    pub fn new(rds: Arc<Mutex<Connection>>, keys: Arc<KeyStore>) -> AuthBeforeMiddleware {
+        AuthBeforeMiddleware { rds, keys }
     }
 }
 
 impl BeforeMiddleware for AuthBeforeMiddleware {
     fn before(&self, req: &mut Request) -> IronResult<()> {
         let response = r#"{"success": false, "error": "Wrong authorization data"}"#;
+        // identity is taken from verified token only, never from client headers
+        req.headers.remove_raw("x-store-id");
+        req.headers.remove_raw("x-user-uuid");
         let rds = self.rds.lock().unwrap();
         let result = match req.headers
             .get::<Authorization<Bearer>>()
             .ok_or(StringError("No auth header".to_string()))
         {
-            Ok(bearer) => match check(&rds, bearer.token.to_owned()) {
-                Ok(data) => Ok(data.claims),
+            Ok(bearer) => match check(&rds, &self.keys, bearer.token.to_owned()) {
+                Ok(claims) => {
+                    if let Err(e) = touch_session(&rds, claims.uuid, claims.device_uuid) {
+                        println!("cannot update session last use: {:?}", e);
+                    };
+                    Ok(claims)
+                }
                 Err(e) => Err(IronError::new(
                     e,
                     (
@@ -57,11 +69,7 @@ impl BeforeMiddleware for AuthBeforeMiddleware {
 
         match result {
             Ok(claims) => {
-                // TODO: use req.extensions.insert instead
-                req.headers
-                    .append_raw("x-store-id", claims.store_id.to_string().into_bytes());
-                req.headers
-                    .append_raw("x-user-uuid", claims.uuid.to_string().into_bytes());
+                req.extensions.insert::<AuthContext>(AuthContext::from(claims));
                 Ok(())
             }
             Err(err) => Err(err),
@@ -69,6 +77,56 @@ impl BeforeMiddleware for AuthBeforeMiddleware {
     }
 }
 
+// Rejects requests made with read-only token
+// of user which email is not verified yet
+pub struct VerifiedOnlyMiddleware;
+
+impl BeforeMiddleware for VerifiedOnlyMiddleware {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let response = r#"{"success": false, "error": "Email is not verified"}"#;
+        match req.extensions.get::<AuthContext>() {
+            Some(ctx) if !ctx.read_only => Ok(()),
+            _ => Err(IronError::new(
+                StringError("Email is not verified".to_string()),
+                (
+                    status::Forbidden,
+                    modifiers::Header(ContentType::json()),
+                    response,
+                ),
+            )),
+        }
+    }
+}
+
+// Allows only users which role is at least required one,
+// must be linked after auth middleware
+pub struct RoleMiddleware {
+    role: Role,
+}
+
+impl RoleMiddleware {
+    pub fn new(role: Role) -> RoleMiddleware {
+        RoleMiddleware { role }
+    }
+}
+
+impl BeforeMiddleware for RoleMiddleware {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let response = r#"{"success": false, "error": "Not enough permissions"}"#;
+        match req.extensions.get::<AuthContext>() {
+            Some(ctx) if ctx.role.allows(self.role) => Ok(()),
+            _ => Err(IronError::new(
+                StringError("Not enough permissions".to_string()),
+                (
+                    status::Forbidden,
+                    modifiers::Header(ContentType::json()),
+                    response,
+                ),
+            )),
+        }
+    }
+}
+
 pub struct NotFound404;
 
 impl AfterMiddleware for NotFound404 {
@@ -1,4 +1,5 @@
 use models;
+use models::user::Role;
 use utils::cache;
 use handlers::*;
 use router::Router;
@@ -14,6 +15,8 @@ use mount::Mount;
 use std::sync::{Arc, Mutex};
 use redis::Connection;
 use utils::s3_uploader;
+use utils::mailer;
+use utils::jwk::{self, KeyStore};
 use utils::pubsub::Manager;
 
 pub fn create_router() -> Chain {
@@ -24,53 +27,247 @@ pub fn create_router() -> Chain {
     let psm = Manager::new(pubsub, redis.clone());
     let ps_manager = Arc::new(Mutex::new(psm));
     let s3_client = Arc::new(Mutex::new(s3_uploader::configure_s3_client()));
-    let handler = Handlers::new(db, redis.clone(), s3_client.clone(), ps_manager.clone());
+    let mailer = Arc::new(Mutex::new(mailer::configure_mailer()));
+    let keys = Arc::new(jwk::configure_keys());
+    let handler = Handlers::new(
+        db,
+        redis.clone(),
+        s3_client.clone(),
+        ps_manager.clone(),
+        mailer.clone(),
+        keys.clone(),
+    );
 
     let mut users_router = Router::new();
 
     users_router.post("/create", handler.user_create, "create_user");
+    users_router.post("/verify_email", handler.user_verify_email, "verify_email");
+    users_router.post(
+        "/verify_email/resend",
+        auth_only(handler.user_verify_email_resend, redis.clone(), keys.clone()),
+        "verify_email_resend",
+    );
     users_router.post("/login", handler.user_login, "login");
+    users_router.post("/login/2fa", handler.user_login_two_factor, "login_two_factor");
+    users_router.post("/refresh", handler.user_refresh, "refresh");
+    users_router.get("/oidc/login", handler.user_oidc_login, "oidc_login");
+    users_router.post("/oidc/callback", handler.user_oidc_callback, "oidc_callback");
+    users_router.post(
+        "/reset_password",
+        handler.user_reset_password,
+        "reset_password",
+    );
+    users_router.post(
+        "/reset_password/confirm",
+        handler.user_reset_password_confirm,
+        "reset_password_confirm",
+    );
     users_router.get(
         "/my_info",
-        auth_only(handler.user_info, redis.clone()),
+        auth_only(handler.user_info, redis.clone(), keys.clone()),
         "my_info",
     );
+    users_router.patch(
+        "/me",
+        verified_only(handler.user_update, redis.clone(), keys.clone()),
+        "user_update",
+    );
+    users_router.post(
+        "/change_password",
+        verified_only(handler.user_change_password, redis.clone(), keys.clone()),
+        "change_password",
+    );
+    users_router.post(
+        "/2fa/enroll",
+        verified_only(handler.user_two_factor_enroll, redis.clone(), keys.clone()),
+        "two_factor_enroll",
+    );
+    users_router.post(
+        "/2fa/confirm",
+        verified_only(handler.user_two_factor_confirm, redis.clone(), keys.clone()),
+        "two_factor_confirm",
+    );
+    users_router.post(
+        "/2fa/disable",
+        verified_only(handler.user_two_factor_disable, redis.clone(), keys.clone()),
+        "two_factor_disable",
+    );
+    users_router.get(
+        "/me/export",
+        auth_only(handler.user_export, redis.clone(), keys.clone()),
+        "user_export",
+    );
+    users_router.delete(
+        "/me",
+        auth_only(handler.user_delete, redis.clone(), keys.clone()),
+        "user_delete",
+    );
+    users_router.post(
+        "/logout",
+        auth_only(handler.user_logout, redis.clone(), keys.clone()),
+        "logout",
+    );
+    users_router.post(
+        "/logout_all",
+        auth_only(handler.user_logout_all, redis.clone(), keys.clone()),
+        "logout_all",
+    );
+    users_router.get(
+        "/sessions",
+        auth_only(handler.user_sessions, redis.clone(), keys.clone()),
+        "sessions",
+    );
+    users_router.delete(
+        "/sessions/:device_uuid",
+        auth_only(handler.user_session_delete, redis.clone(), keys.clone()),
+        "session_delete",
+    );
+    users_router.get(
+        "/list",
+        require_role(handler.user_list, Role::Admin, redis.clone(), keys.clone()),
+        "user_list",
+    );
+    users_router.put(
+        "/:uuid/role",
+        require_role(handler.user_set_role, Role::Admin, redis.clone(), keys.clone()),
+        "user_set_role",
+    );
+    users_router.delete(
+        "/lockout",
+        require_role(handler.user_clear_lockout, Role::Admin, redis.clone(), keys.clone()),
+        "user_clear_lockout",
+    );
 
     let mut ingredient_router = Router::new();
     ingredient_router.get(
         "/list",
-        auth_only(handler.ingredient_list, redis.clone()),
+        auth_only(handler.ingredient_list, redis.clone(), keys.clone()),
         "ingredient_list",
     );
 
     let mut tag_router = Router::new();
     tag_router.get(
         "/list",
-        auth_only(handler.tag_list, redis.clone()),
+        auth_only(handler.tag_list, redis.clone(), keys.clone()),
         "tag_list",
     );
 
     let mut store_router = Router::new();
     store_router.get("/list", handler.store_list, "store_list");
+    store_router.post(
+        "/invites",
+        require_role(handler.invite_create, Role::Admin, redis.clone(), keys.clone()),
+        "invite_create",
+    );
+    store_router.get(
+        "/invites",
+        require_role(handler.invite_list, Role::Admin, redis.clone(), keys.clone()),
+        "invite_list",
+    );
+    store_router.delete(
+        "/invites/:uuid",
+        require_role(handler.invite_revoke, Role::Admin, redis.clone(), keys.clone()),
+        "invite_revoke",
+    );
+    store_router.get(
+        "/accept-mode",
+        auth_only(handler.accept_mode_get, redis.clone(), keys.clone()),
+        "accept_mode_get",
+    );
+    store_router.put(
+        "/accept-mode",
+        require_role(handler.accept_mode_set, Role::Admin, redis.clone(), keys.clone()),
+        "accept_mode_set",
+    );
+    store_router.get(
+        "/kitchen",
+        auth_only(handler.kitchen_get, redis.clone(), keys.clone()),
+        "kitchen_get",
+    );
+    store_router.put(
+        "/kitchen",
+        require_role(handler.kitchen_set, Role::Admin, redis.clone(), keys.clone()),
+        "kitchen_set",
+    );
 
     let mut pizza_router = Router::new();
     let mut chain = Chain::new(handler.pizza_create);
     let interceptor = Intercept::default().file_size_limit(5 << 20);
     chain.link_before(interceptor);
-    pizza_router.post("/create", auth_only(chain, redis.clone()), "pizza_create");
+    pizza_router.post(
+        "/create",
+        verified_only(chain, redis.clone(), keys.clone()),
+        "pizza_create",
+    );
+    pizza_router.post(
+        "/quote",
+        auth_only(handler.pizza_quote, redis.clone(), keys.clone()),
+        "pizza_quote",
+    );
     pizza_router.get(
         "/list",
-        auth_only(handler.pizza_list, redis.clone()),
+        auth_only(handler.pizza_list, redis.clone(), keys.clone()),
         "pizza_list",
     );
+    pizza_router.get(
+        "/history",
+        auth_only(handler.pizza_history, redis.clone(), keys.clone()),
+        "pizza_history",
+    );
+    pizza_router.get(
+        "/:uuid",
+        auth_only(handler.pizza_get, redis.clone(), keys.clone()),
+        "pizza_get",
+    );
+    pizza_router.get(
+        "/:uuid/queue",
+        auth_only(handler.pizza_queue, redis.clone(), keys.clone()),
+        "pizza_queue",
+    );
+    let mut chain = Chain::new(handler.pizza_update);
+    let interceptor = Intercept::default().file_size_limit(5 << 20);
+    chain.link_before(interceptor);
+    pizza_router.patch(
+        "/:uuid",
+        verified_only(chain, redis.clone(), keys.clone()),
+        "pizza_update",
+    );
+    pizza_router.delete(
+        "/:uuid",
+        verified_only(handler.pizza_delete, redis.clone(), keys.clone()),
+        "pizza_delete",
+    );
+    pizza_router.post(
+        "/:uuid/restore",
+        verified_only(handler.pizza_restore, redis.clone(), keys.clone()),
+        "pizza_restore",
+    );
+    pizza_router.post(
+        "/:uuid/status",
+        require_role(handler.pizza_set_status, Role::Cook, redis.clone(), keys.clone()),
+        "pizza_set_status",
+    );
+    pizza_router.post(
+        "/:uuid/accept",
+        require_role(handler.pizza_accept, Role::Cook, redis.clone(), keys.clone()),
+        "pizza_accept",
+    );
+    pizza_router.post(
+        "/:uuid/reject",
+        require_role(handler.pizza_reject, Role::Cook, redis.clone(), keys.clone()),
+        "pizza_reject",
+    );
 
     let mut ws_router = Router::new();
     ws_router.get(
         "/ticket",
-        auth_only(handler.ws_ticket, redis.clone()),
+        auth_only(handler.ws_ticket, redis.clone(), keys.clone()),
         "ticket",
     );
 
+    let mut well_known_router = Router::new();
+    well_known_router.get("/jwks.json", handler.jwks, "jwks");
+
     let mut index_router = Router::new();
     index_router.get("/", handler.index_handler, "index");
 
@@ -81,6 +278,7 @@ pub fn create_router() -> Chain {
     mount.mount("/api/v1/store", store_router);
     mount.mount("/api/v1/pizza", pizza_router);
     mount.mount("/api/v1/ws", ws_router);
+    mount.mount("/.well-known", well_known_router);
     mount.mount("/", index_router);
 
     apply_middlewares(mount)
@@ -102,9 +300,34 @@ fn apply_middlewares(mount: Mount) -> Chain {
     chain
 }
 
-fn auth_only<H: Handler>(handler: H, rds: Arc<Mutex<Connection>>) -> Chain {
-    let auth_only_middleware = middlewares::AuthBeforeMiddleware::new(rds);
+fn auth_only<H: Handler>(
+    handler: H,
+    rds: Arc<Mutex<Connection>>,
+    keys: Arc<KeyStore>,
+) -> Chain {
+    let auth_only_middleware = middlewares::AuthBeforeMiddleware::new(rds, keys);
     let mut chain = Chain::new(handler);
     chain.link_before(auth_only_middleware);
     chain
 }
+
+fn require_role<H: Handler>(
+    handler: H,
+    role: Role,
+    rds: Arc<Mutex<Connection>>,
+    keys: Arc<KeyStore>,
+) -> Chain {
+    let mut chain = verified_only(handler, rds, keys);
+    chain.link_before(middlewares::RoleMiddleware::new(role));
+    chain
+}
+
+fn verified_only<H: Handler>(
+    handler: H,
+    rds: Arc<Mutex<Connection>>,
+    keys: Arc<KeyStore>,
+) -> Chain {
+    let mut chain = auth_only(handler, rds, keys);
+    chain.link_before(middlewares::VerifiedOnlyMiddleware);
+    chain
+}
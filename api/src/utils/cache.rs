@@ -1,7 +1,11 @@
 use redis::{Client, Commands, Connection, ErrorKind, PubSub, RedisError, RedisResult};
+use std::cmp::Reverse;
 use std::sync::MutexGuard;
 use uuid::Uuid;
 use serde_json;
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashMap;
+use utils::random_token;
 
 #[derive(Serialize)]
 pub struct WsTicket {
@@ -10,7 +14,50 @@ pub struct WsTicket {
     pub store_id: i32,
 }
 
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub device_uuid: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_used: DateTime<Utc>,
+    pub user_agent: String,
+    pub remote_ip: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RefreshSession {
+    pub uuid: Uuid,
+    pub device_uuid: Uuid,
+    pub store_id: i32,
+}
+
+pub enum RefreshTokenState {
+    Valid(RefreshSession),
+    Reused(RefreshSession),
+    Invalid,
+}
+
+// Single-use ticket sent to user by email
+#[derive(Serialize, Deserialize)]
+pub struct UserTicket {
+    pub user_uuid: Uuid,
+    pub store_id: i32,
+}
+
+// Pending OpenID Connect login, kept by its state parameter
+#[derive(Serialize, Deserialize)]
+pub struct OidcLoginState {
+    pub store_id: i32,
+    pub nonce: String,
+}
+
 pub const WS_TICKET_EXPIRATION_TIME: usize = 60;
+pub const PASSWORD_RESET_TICKET: &str = "password-reset";
+pub const PASSWORD_RESET_EXPIRATION_TIME: usize = 60 * 60;
+pub const EMAIL_VERIFICATION_TICKET: &str = "email-verification";
+pub const EMAIL_VERIFICATION_EXPIRATION_TIME: usize = 24 * 60 * 60;
+pub const LOGIN_CHALLENGE_TICKET: &str = "login-challenge";
+pub const LOGIN_CHALLENGE_EXPIRATION_TIME: usize = 5 * 60;
+pub const OIDC_STATE_EXPIRATION_TIME: usize = 10 * 60;
 
 pub fn create_redis_connection() -> (Connection, PubSub) {
     let client = Client::open("redis://127.0.0.1:6379").expect("Cannot dial redis");
@@ -22,25 +69,86 @@ pub fn create_redis_connection() -> (Connection, PubSub) {
     )
 }
 
+fn session_key(uuid: Uuid, device_uuid: Uuid) -> String {
+    uuid.to_string() + &device_uuid.to_string()
+}
+
+fn user_sessions_key(uuid: Uuid) -> String {
+    format!("user-sessions-{}", uuid)
+}
+
+fn session_info_key(uuid: Uuid, device_uuid: Uuid) -> String {
+    format!("session-info-{}{}", uuid, device_uuid)
+}
+
+fn refresh_token_key(token: &str) -> String {
+    format!("refresh-token-{}", token)
+}
+
+fn used_refresh_token_key(token: &str) -> String {
+    format!("refresh-token-used-{}", token)
+}
+
+fn device_refresh_token_key(uuid: Uuid, device_uuid: Uuid) -> String {
+    format!("refresh-device-{}{}", uuid, device_uuid)
+}
+
+fn set_until(
+    rds: &MutexGuard<Connection>,
+    key: String,
+    value: String,
+    exp: i64,
+) -> RedisResult<()> {
+    match rds.set::<String, String, String>(key.clone(), value) {
+        Ok(_) => rds.expire_at::<String, ()>(key, exp as usize),
+        Err(e) => Err(e),
+    }
+}
+
 pub fn set_session(
     rds: &MutexGuard<Connection>,
     uuid: Uuid,
     device_uuid: Uuid,
     secret: Uuid,
     exp: i64,
+    user_agent: &str,
+    remote_ip: &str,
 ) -> RedisResult<(String, Uuid)> {
-    match rds.set::<String, String, String>(
-        uuid.to_string() + &device_uuid.to_string(),
-        secret.to_string(),
-    ) {
-        Ok(_) => if let Err(e) = rds.expire_at::<String, usize>(
-            uuid.to_string() + &device_uuid.to_string(),
-            exp as usize,
-        ) {
-            Err(e)
-        } else {
+    match rds.set::<String, String, String>(session_key(uuid, device_uuid), secret.to_string()) {
+        Ok(_) => {
+            if let Err(e) =
+                rds.expire_at::<String, usize>(session_key(uuid, device_uuid), exp as usize)
+            {
+                return Err(e);
+            };
+            let now = Utc::now().timestamp().to_string();
+            if let Err(e) = rds.hset_multiple::<String, &str, &str, ()>(
+                session_info_key(uuid, device_uuid),
+                &[
+                    ("created_at", now.as_ref()),
+                    ("last_used", now.as_ref()),
+                    ("user_agent", user_agent),
+                    ("remote_ip", remote_ip),
+                ],
+            ) {
+                return Err(e);
+            };
+            if let Err(e) =
+                rds.expire_at::<String, usize>(session_info_key(uuid, device_uuid), exp as usize)
+            {
+                return Err(e);
+            };
+            // keep index of user devices to be able to revoke all of them
+            if let Err(e) =
+                rds.sadd::<String, String, i32>(user_sessions_key(uuid), device_uuid.to_string())
+            {
+                return Err(e);
+            };
+            if let Err(e) = rds.expire_at::<String, usize>(user_sessions_key(uuid), exp as usize) {
+                return Err(e);
+            };
             Ok((secret.to_string(), device_uuid))
-        },
+        }
         Err(e) => Err(e),
     }
 }
@@ -50,7 +158,233 @@ pub fn get_session(
     uuid: Uuid,
     device_uuid: Uuid,
 ) -> RedisResult<String> {
-    rds.get(uuid.to_string() + &device_uuid.to_string())
+    rds.get(session_key(uuid, device_uuid))
+}
+
+// Prolongs device session, used upon access token refresh
+pub fn extend_session(
+    rds: &MutexGuard<Connection>,
+    uuid: Uuid,
+    device_uuid: Uuid,
+    exp: i64,
+) -> RedisResult<()> {
+    for key in [session_key(uuid, device_uuid),
+        session_info_key(uuid, device_uuid),
+        user_sessions_key(uuid)] {
+        if let Err(e) = rds.expire_at::<String, usize>(key, exp as usize) {
+            return Err(e);
+        };
+    }
+    Ok(())
+}
+
+pub fn touch_session(
+    rds: &MutexGuard<Connection>,
+    uuid: Uuid,
+    device_uuid: Uuid,
+) -> RedisResult<()> {
+    let key = session_info_key(uuid, device_uuid);
+    match rds.exists::<String, bool>(key.clone()) {
+        Ok(true) => rds.hset::<String, &str, String, ()>(
+            key,
+            "last_used",
+            Utc::now().timestamp().to_string(),
+        ),
+        Ok(false) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn has_session(
+    rds: &MutexGuard<Connection>,
+    uuid: Uuid,
+    device_uuid: Uuid,
+) -> RedisResult<bool> {
+    rds.sismember(user_sessions_key(uuid), device_uuid.to_string())
+}
+
+pub fn get_sessions(rds: &MutexGuard<Connection>, uuid: Uuid) -> RedisResult<Vec<SessionInfo>> {
+    let devices: Vec<String> = match rds.smembers(user_sessions_key(uuid)) {
+        Ok(devices) => devices,
+        Err(e) => return Err(e),
+    };
+    let mut sessions = Vec::new();
+    for device in devices.iter() {
+        let device_uuid = match Uuid::parse_str(device) {
+            Ok(device_uuid) => device_uuid,
+            Err(_) => continue,
+        };
+        let info: HashMap<String, String> =
+            match rds.hgetall(session_info_key(uuid, device_uuid)) {
+                Ok(info) => info,
+                Err(e) => return Err(e),
+            };
+        // info expires together with session secret
+        if info.is_empty() {
+            continue;
+        }
+        sessions.push(SessionInfo {
+            device_uuid,
+            created_at: timestamp_field(&info, "created_at"),
+            last_used: timestamp_field(&info, "last_used"),
+            user_agent: info.get("user_agent").cloned().unwrap_or_default(),
+            remote_ip: info.get("remote_ip").cloned().unwrap_or_default(),
+        });
+    }
+    sessions.sort_by_key(|session| Reverse(session.last_used));
+    Ok(sessions)
+}
+
+fn timestamp_field(info: &HashMap<String, String>, field: &str) -> DateTime<Utc> {
+    let ts = info.get(field)
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(0);
+    Utc.timestamp(ts, 0)
+}
+
+pub fn delete_session(
+    rds: &MutexGuard<Connection>,
+    uuid: Uuid,
+    device_uuid: Uuid,
+) -> RedisResult<()> {
+    if let Err(e) = rds.del::<String, i32>(session_key(uuid, device_uuid)) {
+        return Err(e);
+    };
+    if let Err(e) = rds.del::<String, i32>(session_info_key(uuid, device_uuid)) {
+        return Err(e);
+    };
+    if let Err(e) = delete_refresh_token(rds, uuid, device_uuid) {
+        return Err(e);
+    };
+    match rds.srem::<String, String, i32>(user_sessions_key(uuid), device_uuid.to_string()) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn delete_all_sessions(rds: &MutexGuard<Connection>, uuid: Uuid) -> RedisResult<()> {
+    if let Err(e) = delete_other_sessions(rds, uuid, None) {
+        return Err(e);
+    };
+    match rds.del::<String, i32>(user_sessions_key(uuid)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// Revokes all user sessions except the one of given device
+pub fn delete_other_sessions(
+    rds: &MutexGuard<Connection>,
+    uuid: Uuid,
+    keep_device_uuid: Option<Uuid>,
+) -> RedisResult<()> {
+    let devices: Vec<String> = match rds.smembers(user_sessions_key(uuid)) {
+        Ok(devices) => devices,
+        Err(e) => return Err(e),
+    };
+    for device in devices.iter() {
+        let device_uuid = match Uuid::parse_str(device) {
+            Ok(device_uuid) => device_uuid,
+            Err(_) => continue,
+        };
+        if Some(device_uuid) == keep_device_uuid {
+            continue;
+        };
+        if let Err(e) = delete_session(rds, uuid, device_uuid) {
+            return Err(e);
+        };
+    }
+    Ok(())
+}
+
+pub fn set_refresh_token(
+    rds: &MutexGuard<Connection>,
+    session: &RefreshSession,
+    exp: i64,
+) -> RedisResult<String> {
+    let token = random_token();
+    let value = match serde_json::to_string(session) {
+        Ok(value) => value,
+        Err(_) => {
+            return Err(RedisError::from(
+                (ErrorKind::IoError, "Error upon serializing refresh token data"),
+            ))
+        }
+    };
+    if let Err(e) = set_until(rds, refresh_token_key(token.as_ref()), value, exp) {
+        return Err(e);
+    };
+    match set_until(
+        rds,
+        device_refresh_token_key(session.uuid, session.device_uuid),
+        token.clone(),
+        exp,
+    ) {
+        Ok(_) => Ok(token),
+        Err(e) => Err(e),
+    }
+}
+
+// Refresh tokens are single-use, used token is remembered
+// till its expiration to be able to detect replay
+pub fn use_refresh_token(
+    rds: &MutexGuard<Connection>,
+    token: &str,
+) -> RedisResult<RefreshTokenState> {
+    let value: Option<String> = match rds.get(refresh_token_key(token)) {
+        Ok(value) => value,
+        Err(e) => return Err(e),
+    };
+    if let Some(value) = value {
+        let ttl: i64 = match redis::cmd("TTL").arg(refresh_token_key(token)).query(&**rds) {
+            Ok(ttl) => ttl,
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = rds.del::<String, i32>(refresh_token_key(token)) {
+            return Err(e);
+        };
+        if ttl > 0 {
+            if let Err(e) = rds.set_ex::<String, String, String>(
+                used_refresh_token_key(token),
+                value.clone(),
+                ttl as usize,
+            ) {
+                return Err(e);
+            };
+        };
+        return match serde_json::from_str::<RefreshSession>(value.as_ref()) {
+            Ok(session) => Ok(RefreshTokenState::Valid(session)),
+            Err(_) => Ok(RefreshTokenState::Invalid),
+        };
+    };
+    let used: Option<String> = match rds.get(used_refresh_token_key(token)) {
+        Ok(used) => used,
+        Err(e) => return Err(e),
+    };
+    match used.map(|v| serde_json::from_str::<RefreshSession>(v.as_ref())) {
+        Some(Ok(session)) => Ok(RefreshTokenState::Reused(session)),
+        _ => Ok(RefreshTokenState::Invalid),
+    }
+}
+
+fn delete_refresh_token(
+    rds: &MutexGuard<Connection>,
+    uuid: Uuid,
+    device_uuid: Uuid,
+) -> RedisResult<()> {
+    let token: Option<String> = match rds.get(device_refresh_token_key(uuid, device_uuid)) {
+        Ok(token) => token,
+        Err(e) => return Err(e),
+    };
+    if let Some(token) = token {
+        if let Err(e) = rds.del::<String, i32>(refresh_token_key(token.as_ref())) {
+            return Err(e);
+        };
+    };
+    match rds.del::<String, i32>(device_refresh_token_key(uuid, device_uuid)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    }
 }
 
 pub fn set_ws_ticket(
@@ -80,3 +414,118 @@ pub fn set_ws_ticket(
         )),
     }
 }
+
+fn user_ticket_key(kind: &str, token: &str) -> String {
+    format!("{}-{}", kind, token)
+}
+
+pub fn set_user_ticket(
+    rds: &MutexGuard<Connection>,
+    kind: &str,
+    token: &str,
+    user_uuid: Uuid,
+    store_id: i32,
+    expiration_time: usize,
+) -> RedisResult<()> {
+    let ticket = UserTicket {
+        user_uuid,
+        store_id,
+    };
+    match serde_json::to_string(&ticket) {
+        Ok(s) => rds.set_ex::<String, String, ()>(
+            user_ticket_key(kind, token),
+            s,
+            expiration_time,
+        ),
+        Err(_) => Err(RedisError::from(
+            (ErrorKind::IoError, "Error upon serializing user ticket data"),
+        )),
+    }
+}
+
+// Reads user ticket leaving it in place for next attempts
+pub fn get_user_ticket(
+    rds: &MutexGuard<Connection>,
+    kind: &str,
+    token: &str,
+) -> RedisResult<Option<UserTicket>> {
+    let value: Option<String> = match rds.get(user_ticket_key(kind, token)) {
+        Ok(value) => value,
+        Err(e) => return Err(e),
+    };
+    Ok(value.and_then(|v| serde_json::from_str::<UserTicket>(v.as_ref()).ok()))
+}
+
+// User ticket is removed upon first read so it cannot be used twice
+pub fn take_user_ticket(
+    rds: &MutexGuard<Connection>,
+    kind: &str,
+    token: &str,
+) -> RedisResult<Option<UserTicket>> {
+    let value: Option<String> = match rds.get(user_ticket_key(kind, token)) {
+        Ok(value) => value,
+        Err(e) => return Err(e),
+    };
+    if let Err(e) = rds.del::<String, i32>(user_ticket_key(kind, token)) {
+        return Err(e);
+    };
+    Ok(value.and_then(|v| serde_json::from_str::<UserTicket>(v.as_ref()).ok()))
+}
+
+fn oidc_state_key(state: &str) -> String {
+    format!("oidc-state-{}", state)
+}
+
+pub fn set_oidc_state(
+    rds: &MutexGuard<Connection>,
+    state: &str,
+    login: &OidcLoginState,
+) -> RedisResult<()> {
+    match serde_json::to_string(login) {
+        Ok(s) => rds.set_ex::<String, String, ()>(
+            oidc_state_key(state),
+            s,
+            OIDC_STATE_EXPIRATION_TIME,
+        ),
+        Err(_) => Err(RedisError::from(
+            (ErrorKind::IoError, "Error upon serializing oidc state"),
+        )),
+    }
+}
+
+// State is single-use, replayed callback finds nothing
+pub fn take_oidc_state(
+    rds: &MutexGuard<Connection>,
+    state: &str,
+) -> RedisResult<Option<OidcLoginState>> {
+    let value: Option<String> = match rds.get(oidc_state_key(state)) {
+        Ok(value) => value,
+        Err(e) => return Err(e),
+    };
+    if let Err(e) = rds.del::<String, i32>(oidc_state_key(state)) {
+        return Err(e);
+    };
+    Ok(value.and_then(|v| serde_json::from_str::<OidcLoginState>(v.as_ref()).ok()))
+}
+
+fn totp_step_key(uuid: Uuid) -> String {
+    format!("totp-step-{}", uuid)
+}
+
+// Remembers last accepted TOTP time step, returns false
+// when code of this or earlier step is replayed
+pub fn use_totp_step(rds: &MutexGuard<Connection>, uuid: Uuid, step: i64) -> RedisResult<bool> {
+    let last: Option<i64> = match rds.get(totp_step_key(uuid)) {
+        Ok(last) => last,
+        Err(e) => return Err(e),
+    };
+    if let Some(last) = last {
+        if step <= last {
+            return Ok(false);
+        }
+    };
+    match rds.set_ex::<String, i64, ()>(totp_step_key(uuid), step, 5 * 60) {
+        Ok(_) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
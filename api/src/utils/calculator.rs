@@ -4,9 +4,10 @@ use chrono::offset::Utc;
 use num_traits::{FromPrimitive, ToPrimitive};
 use postgres::{Connection, Error};
 use chrono::TimeZone;
-use postgres::types::ToSql;
 use std::sync::MutexGuard;
 use std::result;
+use std::cmp::max;
+use chrono::Duration;
 
 const PIZZA_BASIC_TIME: i64 = 300;
 const PIZZA_SIZE_TIME_FACTOR: i64 = 5;
@@ -14,45 +15,186 @@ const PIZZA_TIME_PER_INGREDIENT: i64 = 5;
 const PIZZA_CRUST_PRICE_FACTOR: i64 = 5;
 type Result<T> = result::Result<T, Error>;
 
+#[derive(Serialize, Debug)]
+pub struct IngredientPrice {
+    pub id: i32,
+    pub name: String,
+    pub price: f64,
+}
+
+// Itemized price of pizza: crust depends on size, each ingredient adds its price
+#[derive(Serialize, Debug)]
+pub struct PriceBreakdown {
+    pub crust: f64,
+    pub ingredients: Vec<IngredientPrice>,
+    pub total: f64,
+}
+
 pub fn calculate_pizza_price(
     db: &MutexGuard<Connection>,
     ingredient_ids: &Vec<i32>,
     size: &i64,
 ) -> Result<f64> {
-    let base_price = Decimal::from(size / PIZZA_CRUST_PRICE_FACTOR);
-    let mut query = ingredient_ids.iter().enumerate().fold(
-        "SELECT price FROM ingredient WHERE id IN (".to_string(),
-        |acc, x| {
-            let (i, _) = x;
-            acc + &format!("${},", i + 1)
-        },
-    );
-    query.pop();
-    query += ") ORDER BY id;";
-    let ids: Vec<&ToSql> = ingredient_ids
-        .iter()
-        .map(|x| {
-            let sq: &ToSql = x;
-            sq
-        })
-        .collect();
-    match db.query(&query, &ids) {
-        Ok(query) => Ok(
+    calculate_price_breakdown(db, ingredient_ids, size).map(|breakdown| breakdown.total)
+}
+
+pub fn calculate_price_breakdown(
+    db: &MutexGuard<Connection>,
+    ingredient_ids: &Vec<i32>,
+    size: &i64,
+) -> Result<PriceBreakdown> {
+    match db.query(
+        "SELECT id, name, price FROM ingredient WHERE id = ANY($1) ORDER BY id;",
+        &[ingredient_ids],
+    ) {
+        Ok(query) => Ok(price_breakdown(
+            size,
             query
                 .iter()
-                .filter_map(|row| Decimal::from_f64(row.get("price")))
-                .fold(base_price, |acc, x| acc + x)
-                .to_f64()
-                .unwrap(),
-        ),
-        Err(err) => Err(Error::from(err)),
+                .map(|row| IngredientPrice {
+                    id: row.get("id"),
+                    name: row.get("name"),
+                    price: row.get("price"),
+                })
+                .collect(),
+        )),
+        Err(err) => Err(err),
+    }
+}
+
+// Prices are summed as decimals, so total is exactly crust plus ingredients
+fn price_breakdown(size: &i64, ingredients: Vec<IngredientPrice>) -> PriceBreakdown {
+    let base_price = Decimal::from(size / PIZZA_CRUST_PRICE_FACTOR);
+    let total = ingredients
+        .iter()
+        .filter_map(|ingredient| Decimal::from_f64(ingredient.price))
+        .fold(base_price, |acc, x| acc + x);
+    PriceBreakdown {
+        crust: base_price.to_f64().unwrap(),
+        ingredients,
+        total: total.to_f64().unwrap(),
     }
 }
 
-pub fn calculate_preparation_time(size: &i64, ingredient_count: usize) -> DateTime<Utc> {
+// Seconds kitchen spends on pizza itself
+pub fn preparation_duration(size: &i64, ingredient_count: usize) -> i64 {
     let base_add_size_time = size / PIZZA_SIZE_TIME_FACTOR * 10;
     let base_add_ingredients_time = ingredient_count as i64 * PIZZA_TIME_PER_INGREDIENT;
-    let result = PIZZA_BASIC_TIME + base_add_size_time + base_add_ingredients_time;
-    let ready_timestamp = Utc::now().naive_utc().timestamp() + result;
-    Utc.timestamp(ready_timestamp, 0)
+    PIZZA_BASIC_TIME + base_add_size_time + base_add_ingredients_time
+}
+
+// Every waiting pizza takes an oven until its time_prepared, so new one gets the oven
+// which is freed first. Besides, staff has to get through not started pizzas before,
+// one per 3600 / throughput seconds. Ready times of waiting pizzas go earliest first
+pub fn estimate_ready_time(
+    ovens: i32,
+    throughput: i32,
+    waiting: &[DateTime<Utc>],
+    not_started: usize,
+    duration: i64,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let ovens = max(ovens, 1) as usize;
+    let oven_free = if waiting.len() < ovens {
+        now
+    } else {
+        max(waiting[waiting.len() - ovens], now)
+    };
+    let staff_interval = 3600 / max(throughput, 1) as i64;
+    let staff_free = now + Duration::seconds(not_started as i64 * staff_interval);
+    let start = max(oven_free, staff_free);
+    Utc.timestamp(start.timestamp() + duration, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // size, ingredient prices, crust and total
+    const PRICES: [(i64, &[f64], f64, f64); 4] = [
+        (30, &[], 6.0, 6.0),
+        (45, &[1.5], 9.0, 10.5),
+        (60, &[0.1, 0.2], 12.0, 12.3),
+        (32, &[2.25, 0.75, 1.0], 6.0, 10.0),
+    ];
+
+    // Preparation duration of pizza being estimated
+    const DURATION: i64 = 400;
+
+    // Kitchen and its queue, times are seconds from now
+    struct Eta {
+        ovens: i32,
+        throughput: i32,
+        // ready times of waiting pizzas
+        waiting: &'static [i64],
+        not_started: usize,
+        ready: i64,
+    }
+
+    const ETAS: [Eta; 8] = [
+        Eta { ovens: 2, throughput: 12, waiting: &[], not_started: 0, ready: 400 },
+        Eta { ovens: 2, throughput: 12, waiting: &[100], not_started: 1, ready: 700 },
+        Eta { ovens: 1, throughput: 12, waiting: &[500], not_started: 0, ready: 900 },
+        Eta { ovens: 2, throughput: 12, waiting: &[200, 500], not_started: 0, ready: 600 },
+        Eta { ovens: 2, throughput: 60, waiting: &[-50, 100], not_started: 0, ready: 400 },
+        Eta { ovens: 2, throughput: 12, waiting: &[200, 500], not_started: 3, ready: 1300 },
+        Eta { ovens: 0, throughput: 0, waiting: &[100], not_started: 0, ready: 500 },
+        Eta { ovens: 1, throughput: 0, waiting: &[], not_started: 2, ready: 7600 },
+    ];
+
+    fn breakdown(size: i64, prices: &[f64]) -> PriceBreakdown {
+        let ingredients = prices
+            .iter()
+            .enumerate()
+            .map(|(i, &price)| IngredientPrice {
+                id: i as i32,
+                name: format!("ingredient {}", i),
+                price,
+            })
+            .collect();
+        price_breakdown(&size, ingredients)
+    }
+
+    // price of created pizza is total of the breakdown quote shows
+    #[test]
+    fn sums_crust_and_ingredient_prices() {
+        for &(size, prices, crust, total) in PRICES.iter() {
+            let breakdown = breakdown(size, prices);
+            assert_eq!(breakdown.crust, crust);
+            assert_eq!(breakdown.total, total);
+            let ingredients: Vec<f64> = breakdown.ingredients.iter().map(|i| i.price).collect();
+            assert_eq!(ingredients, prices.to_vec());
+        }
+    }
+
+    #[test]
+    fn estimates_ready_time_by_ovens_and_staff() {
+        let now = Utc.timestamp(1_000_000, 0);
+        for eta in ETAS.iter() {
+            let waiting: Vec<DateTime<Utc>> = eta.waiting
+                .iter()
+                .map(|&secs| now + Duration::seconds(secs))
+                .collect();
+            assert_eq!(
+                estimate_ready_time(
+                    eta.ovens,
+                    eta.throughput,
+                    &waiting,
+                    eta.not_started,
+                    DURATION,
+                    now
+                ),
+                now + Duration::seconds(eta.ready)
+            );
+        }
+    }
+
+    #[test]
+    fn estimates_ready_time_in_whole_seconds() {
+        let now = Utc.timestamp(1_000_000, 500_000_000);
+        assert_eq!(
+            estimate_ready_time(1, 12, &[], 0, preparation_duration(&30, 2), now),
+            Utc.timestamp(1_000_000 + 300 + 60 + 10, 0)
+        );
+    }
 }
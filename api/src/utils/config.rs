@@ -0,0 +1,72 @@
+use std::env;
+use std::str::FromStr;
+
+// Default lifetimes of auth tokens in seconds
+const DEFAULT_ACCESS_TOKEN_LIFETIME: i64 = 15 * 60;
+const DEFAULT_REFRESH_TOKEN_LIFETIME: i64 = 30 * 24 * 60 * 60;
+
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(val) => val.parse::<T>().unwrap_or(default),
+        Err(_) => default,
+    }
+}
+
+pub fn access_token_lifetime() -> i64 {
+    env_or("ACCESS_TOKEN_LIFETIME", DEFAULT_ACCESS_TOKEN_LIFETIME)
+}
+
+pub fn refresh_token_lifetime() -> i64 {
+    env_or("REFRESH_TOKEN_LIFETIME", DEFAULT_REFRESH_TOKEN_LIFETIME)
+}
+
+pub fn email_verification_enabled() -> bool {
+    env_or("EMAIL_VERIFICATION", false)
+}
+
+// Failed login attempts allowed before lockout
+pub fn login_max_attempts() -> i64 {
+    env_or("LOGIN_MAX_ATTEMPTS", 5)
+}
+
+pub fn login_max_attempts_per_ip() -> i64 {
+    env_or("LOGIN_MAX_ATTEMPTS_PER_IP", 20)
+}
+
+// Failed attempts are forgotten after this amount of seconds
+pub fn login_failures_window() -> i64 {
+    env_or("LOGIN_FAILURES_WINDOW", 15 * 60)
+}
+
+// First lockout duration in seconds, doubled with every next failure
+pub fn login_lockout_base() -> i64 {
+    env_or("LOGIN_LOCKOUT_BASE", 30)
+}
+
+pub fn login_lockout_max() -> i64 {
+    env_or("LOGIN_LOCKOUT_MAX", 60 * 60)
+}
+
+// Header with client address set by reverse proxy in front of API, e.g. X-Forwarded-For
+// or X-Real-IP, address of connection is used when it is not set
+pub fn trusted_proxy_header() -> Option<String> {
+    match env::var("TRUSTED_PROXY_HEADER") {
+        Ok(header) => if header.is_empty() {
+            None
+        } else {
+            Some(header)
+        },
+        Err(_) => None,
+    }
+}
+
+// What happens with pizzas of deleted account: "anonymize" (default)
+// detaches them from user, "remove" deletes them with images
+pub fn account_deletion_removes_pizzas() -> bool {
+    env_or("ACCOUNT_DELETION_POLICY", "anonymize".to_string()) == "remove"
+}
+
+// Creates member of requested store upon first OpenID Connect login of unknown subject
+pub fn oidc_auto_provision() -> bool {
+    env_or("OIDC_AUTO_PROVISION", false)
+}
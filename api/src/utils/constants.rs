@@ -1,3 +1,10 @@
-pub const NOTIFICATION_THREAD_NAME: &'static str = "NOTIFICATION";
-pub const CREATE_PIZZA_EVENT_NAME: &'static str = "CREATE_PIZZA";
-pub const ACCEPT_PIZZA_EVENT_NAME: &'static str = "ACCEPT_PIZZA";
+pub const NOTIFICATION_THREAD_NAME: &str = "NOTIFICATION";
+pub const CREATE_PIZZA_EVENT_NAME: &str = "CREATE_PIZZA";
+pub const ACCEPT_PIZZA_EVENT_NAME: &str = "ACCEPT_PIZZA";
+pub const DELETE_PIZZA_EVENT_NAME: &str = "DELETE_PIZZA";
+pub const RESTORE_PIZZA_EVENT_NAME: &str = "RESTORE_PIZZA";
+pub const UPDATE_PIZZA_EVENT_NAME: &str = "UPDATE_PIZZA";
+pub const PREPARE_PIZZA_EVENT_NAME: &str = "PREPARE_PIZZA";
+pub const BAKE_PIZZA_EVENT_NAME: &str = "BAKE_PIZZA";
+pub const PICK_UP_PIZZA_EVENT_NAME: &str = "PICK_UP_PIZZA";
+pub const REJECT_PIZZA_EVENT_NAME: &str = "REJECT_PIZZA";
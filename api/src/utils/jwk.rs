@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use base64;
+use jwt::{sign, verify, Algorithm};
+use jwt::errors::{ErrorKind, Result};
+use ring::signature::{self, Ed25519KeyPair};
+use untrusted::Input;
+use utils::config::env_or;
+
+// Public part of every key is stored in "<kid>.pub.der",
+// private part of signing key - in "<kid>.der"
+const PUBLIC_KEY_SUFFIX: &str = ".pub.der";
+const PRIVATE_KEY_SUFFIX: &str = ".der";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyAlgorithm {
+    RS256,
+    EdDSA,
+}
+
+impl KeyAlgorithm {
+    pub fn from_str(s: &str) -> Option<KeyAlgorithm> {
+        match s {
+            "RS256" => Some(KeyAlgorithm::RS256),
+            "EdDSA" => Some(KeyAlgorithm::EdDSA),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            KeyAlgorithm::RS256 => "RS256",
+            KeyAlgorithm::EdDSA => "EdDSA",
+        }
+    }
+}
+
+// RS256 keys are PKCS#1 DER, EdDSA keys are raw 32 bytes
+// of Ed25519 seed and public key
+pub struct JwtKey {
+    pub algorithm: KeyAlgorithm,
+    public_key: Vec<u8>,
+    private_key: Option<Vec<u8>>,
+}
+
+impl JwtKey {
+    pub fn sign(&self, signing_input: &str) -> Result<String> {
+        match (self.algorithm, &self.private_key) {
+            (_, &None) => Err(ErrorKind::InvalidKey.into()),
+            (KeyAlgorithm::RS256, Some(key)) => sign(signing_input, key, Algorithm::RS256),
+            (KeyAlgorithm::EdDSA, Some(seed)) => {
+                match Ed25519KeyPair::from_seed_and_public_key(
+                    Input::from(seed),
+                    Input::from(&self.public_key),
+                ) {
+                    Ok(pair) => Ok(base64::encode_config(
+                        pair.sign(signing_input.as_bytes()).as_ref(),
+                        base64::URL_SAFE_NO_PAD,
+                    )),
+                    Err(_) => Err(ErrorKind::InvalidKey.into()),
+                }
+            }
+        }
+    }
+
+    pub fn verify(&self, sig: &str, signing_input: &str) -> Result<bool> {
+        match self.algorithm {
+            KeyAlgorithm::RS256 => verify(sig, signing_input, &self.public_key, Algorithm::RS256),
+            KeyAlgorithm::EdDSA => match base64::decode_config(sig, base64::URL_SAFE_NO_PAD) {
+                Ok(sig) => Ok(signature::verify(
+                    &signature::ED25519,
+                    Input::from(&self.public_key),
+                    Input::from(signing_input.as_bytes()),
+                    Input::from(&sig),
+                ).is_ok()),
+                Err(_) => Err(ErrorKind::InvalidSignature.into()),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Jwk {
+    kty: &'static str,
+    #[serde(rename = "use")]
+    usage: &'static str,
+    alg: &'static str,
+    kid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+}
+
+// Without asymmetric algorithm configured tokens are signed
+// with HS256 session secrets and key store stays empty
+pub struct KeyStore {
+    signing_kid: Option<String>,
+    keys: HashMap<String, JwtKey>,
+    jwks: Vec<Jwk>,
+}
+
+impl KeyStore {
+    pub fn signing_key(&self) -> Option<(&str, &JwtKey)> {
+        match self.signing_kid {
+            Some(ref kid) => self.keys.get(kid).map(|key| (kid.as_ref(), key)),
+            None => None,
+        }
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&JwtKey> {
+        self.keys.get(kid)
+    }
+
+    pub fn jwks(&self) -> &Vec<Jwk> {
+        &self.jwks
+    }
+}
+
+// JWT_ALGORITHM=RS256|EdDSA enables asymmetric signing. Every public key
+// found in JWT_KEYS_DIR is accepted, so retired keys are kept there
+// until their tokens expire, JWT_SIGNING_KID selects key for new tokens
+pub fn configure_keys() -> KeyStore {
+    let mut store = KeyStore {
+        signing_kid: None,
+        keys: HashMap::new(),
+        jwks: Vec::new(),
+    };
+    let algorithm =
+        match KeyAlgorithm::from_str(env_or("JWT_ALGORITHM", "HS256".to_string()).as_ref()) {
+            Some(algorithm) => algorithm,
+            None => return store,
+        };
+    let dir = env_or("JWT_KEYS_DIR", "keys".to_string());
+    let signing_kid = env::var("JWT_SIGNING_KID").expect("JWT_SIGNING_KID is not set");
+
+    for entry in fs::read_dir(&dir).expect("Cannot read JWT_KEYS_DIR") {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(_) => continue,
+        };
+        let kid = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.ends_with(PUBLIC_KEY_SUFFIX) => {
+                name.trim_end_matches(PUBLIC_KEY_SUFFIX).to_string()
+            }
+            _ => continue,
+        };
+        let public_key = match read_key(&path) {
+            Some(public_key) => public_key,
+            None => continue,
+        };
+        let jwk = match to_jwk(algorithm, kid.as_ref(), &public_key) {
+            Some(jwk) => jwk,
+            None => {
+                println!("skipping malformed {} public key {}", algorithm.as_str(), kid);
+                continue;
+            }
+        };
+        store.jwks.push(jwk);
+        store.keys.insert(
+            kid,
+            JwtKey {
+                algorithm,
+                public_key,
+                private_key: None,
+            },
+        );
+    }
+
+    let private_path = Path::new(&dir).join(signing_kid.clone() + PRIVATE_KEY_SUFFIX);
+    let private_key = read_key(&private_path).expect("Cannot read private key of JWT_SIGNING_KID");
+    match store.keys.get_mut(&signing_kid) {
+        Some(key) => {
+            key.private_key = Some(private_key);
+            // mismatched key pair would issue tokens nobody can verify
+            let signature = key.sign("").expect("Cannot sign with JWT_SIGNING_KID key");
+            if !key.verify(signature.as_ref(), "").unwrap_or(false) {
+                panic!("Private and public keys of JWT_SIGNING_KID do not match");
+            }
+        }
+        None => panic!("Public key of JWT_SIGNING_KID is not found"),
+    };
+    store.signing_kid = Some(signing_kid);
+    store
+}
+
+fn read_key(path: &Path) -> Option<Vec<u8>> {
+    let mut key = Vec::new();
+    match File::open(path).and_then(|mut file| file.read_to_end(&mut key)) {
+        Ok(_) => Some(key),
+        Err(e) => {
+            println!("cannot read key {:?}: {:?}", path, e);
+            None
+        }
+    }
+}
+
+fn to_jwk(algorithm: KeyAlgorithm, kid: &str, public_key: &[u8]) -> Option<Jwk> {
+    match algorithm {
+        KeyAlgorithm::RS256 => rsa_components(public_key).map(|(n, e)| Jwk {
+            kty: "RSA",
+            usage: "sig",
+            alg: algorithm.as_str(),
+            kid: kid.to_string(),
+            n: Some(base64::encode_config(&n, base64::URL_SAFE_NO_PAD)),
+            e: Some(base64::encode_config(&e, base64::URL_SAFE_NO_PAD)),
+            crv: None,
+            x: None,
+        }),
+        KeyAlgorithm::EdDSA if public_key.len() == 32 => Some(Jwk {
+            kty: "OKP",
+            usage: "sig",
+            alg: algorithm.as_str(),
+            kid: kid.to_string(),
+            n: None,
+            e: None,
+            crv: Some("Ed25519"),
+            x: Some(base64::encode_config(public_key, base64::URL_SAFE_NO_PAD)),
+        }),
+        KeyAlgorithm::EdDSA => None,
+    }
+}
+
+// Modulus and exponent of PKCS#1 RSAPublicKey
+fn rsa_components(der: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (sequence, _) = der_element(der, 0x30)?;
+    let (n, rest) = der_element(sequence, 0x02)?;
+    let (e, _) = der_element(rest, 0x02)?;
+    Some((unsigned(n), unsigned(e)))
+}
+
+// Splits DER data into content of leading element and the rest
+fn der_element(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    if data.len() < 2 || data[0] != tag {
+        return None;
+    }
+    let (len, header) = if data[1] < 0x80 {
+        (data[1] as usize, 2)
+    } else {
+        let octets = (data[1] & 0x7f) as usize;
+        if octets == 0 || octets > 4 || data.len() < 2 + octets {
+            return None;
+        }
+        let len = data[2..2 + octets]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        (len, 2 + octets)
+    };
+    if data.len() < header + len {
+        return None;
+    }
+    Some((&data[header..header + len], &data[header + len..]))
+}
+
+fn unsigned(integer: &[u8]) -> Vec<u8> {
+    let start = integer
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(integer.len());
+    integer[start..].to_vec()
+}
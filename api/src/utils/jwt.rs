@@ -1,52 +1,118 @@
-use jwt::{encode, verify, Algorithm, Header, TokenData};
+use jwt::{encode, verify, Algorithm, Header};
 use uuid::Uuid;
 use chrono::offset::Utc;
 use jwt::errors::{ErrorKind, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use serde_json;
 use base64;
 use std::sync::MutexGuard;
 use redis::Connection;
 use utils::cache::get_session;
+use iron::typemap::Key;
+use models::user::Role;
+use utils::jwk::KeyStore;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     exp: i64,
     pub username: String,
     pub uuid: Uuid,
     pub device_uuid: Uuid,
     pub store_id: i32,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub role: Role,
 }
 
+// Verified identity of request, put into request extensions
+// by auth middleware after token check
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    pub uuid: Uuid,
+    pub device_uuid: Uuid,
+    pub store_id: i32,
+    pub read_only: bool,
+    pub role: Role,
+}
+
+impl From<Claims> for AuthContext {
+    fn from(claims: Claims) -> AuthContext {
+        AuthContext {
+            uuid: claims.uuid,
+            device_uuid: claims.device_uuid,
+            store_id: claims.store_id,
+            read_only: claims.read_only,
+            role: claims.role,
+        }
+    }
+}
+
+impl Key for AuthContext {
+    type Value = AuthContext;
+}
+
+#[derive(Serialize, Deserialize)]
+struct JwtHeader {
+    #[serde(default)]
+    typ: String,
+    alg: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn generate(
+    keys: &KeyStore,
     username: &str,
     uuid: Uuid,
     secret: String,
     device_uuid: Uuid,
     exp: i64,
     store_id: i32,
+    read_only: bool,
+    role: Role,
 ) -> Result<String> {
-    let full_secret = format!("{}_{}_{}", store_id, secret, exp);
     let claims = Claims {
         exp,
         username: username.to_string(),
         uuid,
         device_uuid,
         store_id,
+        read_only,
+        role,
     };
 
-    encode(&Header::default(), &claims, full_secret.as_ref())
+    match keys.signing_key() {
+        Some((kid, key)) => {
+            let header = JwtHeader {
+                typ: "JWT".to_string(),
+                alg: key.algorithm.as_str().to_string(),
+                kid: Some(kid.to_string()),
+            };
+            let signing_input = encode_part(&header)? + "." + &encode_part(&claims)?;
+            let signature = key.sign(signing_input.as_ref())?;
+            Ok(signing_input + "." + &signature)
+        }
+        None => {
+            let full_secret = format!("{}_{}_{}", store_id, secret, exp);
+            encode(&Header::default(), &claims, full_secret.as_ref())
+        }
+    }
 }
 
-pub fn check(rds: &MutexGuard<Connection>, token: String) -> Result<TokenData<Claims>> {
+// HS256 tokens are signed with session secret, asymmetric ones
+// with key chosen by kid. Session must exist in both cases,
+// so logout revokes token before it expires
+pub fn check(rds: &MutexGuard<Connection>, keys: &KeyStore, token: String) -> Result<Claims> {
     let b64: Vec<_> = token.split(".").collect();
     if b64.len() != 3 {
         return Err(ErrorKind::InvalidToken.into());
     }
 
-    let claims: Claims = match decode_payload(b64[1]) {
-        Ok(claims) => claims,
-        Err(e) => return Err(e),
-    };
+    let header: JwtHeader = decode_part(b64[0])?;
+    let claims: Claims = decode_part(b64[1])?;
 
     if claims.exp < Utc::now().naive_utc().timestamp() {
         return Err(ErrorKind::ExpiredSignature.into());
@@ -57,33 +123,29 @@ pub fn check(rds: &MutexGuard<Connection>, token: String) -> Result<TokenData<Cl
         Err(_) => return Err(ErrorKind::InvalidSignature.into()),
     };
 
-    if let Ok(valid) = verify_signature(
-        b64[2].to_string(),
-        (b64[0].to_owned() + "." + b64[1]),
-        claims.exp,
-        secret,
-        claims.store_id,
-    ) {
-        if valid {
-            Ok(TokenData {
-                header: Header::default(),
-                claims,
-            })
-        } else {
-            Err(ErrorKind::InvalidSignature.into())
-        }
+    let signing_input = b64[0].to_owned() + "." + b64[1];
+    let verified = if header.alg == "HS256" {
+        verify_signature(
+            b64[2].to_string(),
+            signing_input,
+            claims.exp,
+            secret,
+            claims.store_id,
+        )
     } else {
-        Err(ErrorKind::InvalidToken.into())
-    }
-}
+        match header.kid.as_ref().and_then(|kid| keys.get(kid)) {
+            Some(key) if key.algorithm.as_str() == header.alg => {
+                key.verify(b64[2], signing_input.as_ref())
+            }
+            _ => Err(ErrorKind::InvalidSignature.into()),
+        }
+    };
 
-pub fn get_claims(token: String) -> Result<Claims> {
-    let b64: Vec<_> = token.split(".").collect();
-    if b64.len() != 3 {
-        return Err(ErrorKind::InvalidToken.into());
+    match verified {
+        Ok(true) => Ok(claims),
+        Ok(false) => Err(ErrorKind::InvalidSignature.into()),
+        Err(_) => Err(ErrorKind::InvalidToken.into()),
     }
-
-    decode_payload(b64[1])
 }
 
 fn verify_signature(
@@ -102,10 +164,17 @@ fn verify_signature(
     )
 }
 
-fn decode_payload(payload_b64: &str) -> Result<Claims> {
-    if let Ok(ref utf_bytes) = base64::decode_config(payload_b64, base64::URL_SAFE_NO_PAD) {
-        if let Ok(user_data) = serde_json::from_slice::<Claims>(utf_bytes) {
-            Ok(user_data)
+fn encode_part<T: Serialize>(part: &T) -> Result<String> {
+    match serde_json::to_vec(part) {
+        Ok(json) => Ok(base64::encode_config(&json, base64::URL_SAFE_NO_PAD)),
+        Err(_) => Err(ErrorKind::InvalidToken.into()),
+    }
+}
+
+fn decode_part<T: DeserializeOwned>(part_b64: &str) -> Result<T> {
+    if let Ok(ref utf_bytes) = base64::decode_config(part_b64, base64::URL_SAFE_NO_PAD) {
+        if let Ok(part) = serde_json::from_slice::<T>(utf_bytes) {
+            Ok(part)
         } else {
             Err(ErrorKind::InvalidToken.into())
         }
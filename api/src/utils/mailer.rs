@@ -0,0 +1,160 @@
+use lettre::{ClientSecurity, SmtpClient, Transport};
+use lettre::smtp::authentication::Credentials;
+use lettre::smtp::client::net::ClientTlsParameters;
+use native_tls::TlsConnector;
+use lettre_email::EmailBuilder;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+use chrono::Utc;
+use utils::config::env_or;
+use utils::types::StringError;
+
+pub type MailResult = Result<(), StringError>;
+
+pub trait Mailer: Send {
+    fn send(&self, to: &str, subject: &str, body: &str) -> MailResult;
+}
+
+// Picks mailer implementation by MAILER env variable:
+// smtp or file (default)
+pub fn configure_mailer() -> Box<dyn Mailer> {
+    match env_or("MAILER", "file".to_string()).as_ref() {
+        "smtp" => Box::new(SmtpMailer {
+            host: env_or("SMTP_HOST", "localhost".to_string()),
+            port: env_or("SMTP_PORT", 25u16),
+            tls: env_or("SMTP_TLS", true),
+            username: env_or("SMTP_USER", String::new()),
+            password: env_or("SMTP_PASSWORD", String::new()),
+            from: mail_from(),
+        }),
+        _ => Box::new(FileMailer {
+            dir: env_or("MAIL_DIR", "mail".to_string()),
+            from: mail_from(),
+        }),
+    }
+}
+
+fn mail_from() -> String {
+    env_or("MAIL_FROM", "noreply@pizza-tele.ga".to_string())
+}
+
+pub struct SmtpMailer {
+    host: String,
+    port: u16,
+    tls: bool,
+    username: String,
+    password: String,
+    from: String,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> MailResult {
+        let email = match EmailBuilder::new()
+            .to(to)
+            .from(self.from.as_ref())
+            .subject(subject)
+            .text(body)
+            .build()
+        {
+            Ok(email) => email,
+            Err(e) => return Err(StringError(format!("Cannot build email: {:?}", e))),
+        };
+        let security = if self.tls {
+            let connector = match TlsConnector::new() {
+                Ok(connector) => connector,
+                Err(e) => return Err(StringError(format!("Cannot set up tls: {:?}", e))),
+            };
+            ClientSecurity::Required(ClientTlsParameters::new(self.host.clone(), connector))
+        } else {
+            ClientSecurity::None
+        };
+        let client = SmtpClient::new((self.host.as_ref(), self.port), security);
+        let mut client = match client {
+            Ok(client) => client,
+            Err(e) => return Err(StringError(format!("Cannot dial smtp server: {:?}", e))),
+        };
+        if !self.username.is_empty() {
+            client = client.credentials(Credentials::new(
+                self.username.clone(),
+                self.password.clone(),
+            ));
+        };
+        match client.transport().send(email.into()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(StringError(format!("Cannot send email: {:?}", e))),
+        }
+    }
+}
+
+// Appends emails to file per recipient, for local development
+pub struct FileMailer {
+    dir: String,
+    from: String,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> MailResult {
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            return Err(StringError(format!("Cannot create mail dir: {:?}", e)));
+        };
+        let path = format!("{}/{}.eml", self.dir, to.replace("/", "_"));
+        let content = format!(
+            "Date: {}\nFrom: {}\nTo: {}\nSubject: {}\n\n{}\n\n",
+            Utc::now().to_rfc2822(),
+            self.from,
+            to,
+            subject,
+            body
+        );
+        match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut f| f.write_all(content.as_bytes()))
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(StringError(format!("Cannot write email: {:?}", e))),
+        }
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct SentMail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Keeps emails in memory, for tests
+#[cfg(test)]
+pub struct MemoryMailer {
+    sent: Arc<Mutex<Vec<SentMail>>>,
+}
+
+#[cfg(test)]
+impl MemoryMailer {
+    pub fn new() -> MemoryMailer {
+        MemoryMailer {
+            sent: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn sent(&self) -> Vec<SentMail> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Mailer for MemoryMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> MailResult {
+        self.sent.lock().unwrap().push(SentMail {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+        });
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 pub mod jwt;
+pub mod jwk;
 pub mod types;
 pub mod cache;
 pub mod s3_uploader;
@@ -6,11 +7,26 @@ pub mod validator;
 pub mod calculator;
 pub mod pubsub;
 pub mod constants;
+pub mod password;
+pub mod config;
+pub mod mailer;
+pub mod oidc;
+pub mod throttle;
+pub mod totp;
 use rand::{OsRng, Rng};
 
 pub fn itob(n: i32) -> bool {
     n != 0
 }
+
+pub fn btoi(b: bool) -> i32 {
+    if b {
+        1
+    } else {
+        0
+    }
+}
+
 pub fn random_token() -> String {
     let mut rand_gen = OsRng::new().unwrap();
     rand_gen.gen_ascii_chars().take(32).collect()
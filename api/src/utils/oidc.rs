@@ -0,0 +1,310 @@
+use std::env;
+use base64;
+use chrono::Utc;
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use utils::config::env_or;
+use utils::types::StringError;
+
+pub type OidcResult<T> = Result<T, StringError>;
+
+// OpenID Connect login is enabled when OIDC_ISSUER is set,
+// issuer must be https, plain http is allowed on loopback for local mock providers
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+pub fn oidc_config() -> Option<OidcConfig> {
+    match env::var("OIDC_ISSUER") {
+        Ok(issuer) => Some(OidcConfig {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id: env_or("OIDC_CLIENT_ID", String::new()),
+            client_secret: env_or("OIDC_CLIENT_SECRET", String::new()),
+            redirect_uri: env_or("OIDC_REDIRECT_URI", String::new()),
+            scopes: env_or("OIDC_SCOPES", "openid email profile".to_string()),
+        }),
+        Err(_) => None,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    iss: String,
+    aud: Value,
+    exp: i64,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+pub fn discover(config: &OidcConfig) -> OidcResult<ProviderMetadata> {
+    check_secure(config.issuer.as_ref())?;
+    let url = format!("{}/.well-known/openid-configuration", config.issuer);
+    let metadata: ProviderMetadata = fetch_json(Client::new().get(url.as_str()).send())?;
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(StringError(format!(
+            "Provider reports issuer {}, expected {}",
+            metadata.issuer, config.issuer
+        )));
+    }
+    check_secure(metadata.authorization_endpoint.as_ref())?;
+    check_secure(metadata.token_endpoint.as_ref())?;
+    Ok(metadata)
+}
+
+// ID token signature is not verified, so provider must be reached over TLS,
+// except for provider running on the same host
+fn check_secure(url: &str) -> OidcResult<()> {
+    let url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(e) => return Err(StringError(format!("Bad provider url {}: {:?}", url, e))),
+    };
+    let loopback = matches!(url.host_str(), Some("localhost") | Some("127.0.0.1") | Some("[::1]"));
+    if url.scheme() == "https" || (url.scheme() == "http" && loopback) {
+        Ok(())
+    } else {
+        Err(StringError(format!("Provider url {} is not https", url)))
+    }
+}
+
+pub fn authorization_url(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    state: &str,
+    nonce: &str,
+) -> OidcResult<String> {
+    match Url::parse_with_params(
+        metadata.authorization_endpoint.as_ref(),
+        &[
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_ref()),
+            ("redirect_uri", config.redirect_uri.as_ref()),
+            ("scope", config.scopes.as_ref()),
+            ("state", state),
+            ("nonce", nonce),
+        ],
+    ) {
+        Ok(url) => Ok(url.into_string()),
+        Err(e) => Err(StringError(format!("Bad authorization endpoint: {:?}", e))),
+    }
+}
+
+// ID token comes straight from token endpoint over TLS back channel (see check_secure),
+// so its signature is not checked, only issuer, audience and expiry
+pub fn exchange_code(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    code: &str,
+) -> OidcResult<IdTokenClaims> {
+    let tokens: TokenResponse = fetch_json(
+        Client::new()
+            .post(metadata.token_endpoint.as_str())
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", config.redirect_uri.as_ref()),
+                ("client_id", config.client_id.as_ref()),
+                ("client_secret", config.client_secret.as_ref()),
+            ])
+            .send(),
+    )?;
+    let claims: IdTokenClaims = match tokens.id_token.split('.').nth(1) {
+        Some(payload) => decode_payload(payload)?,
+        None => return Err(StringError("Malformed id token".to_string())),
+    };
+    if claims.iss.trim_end_matches('/') != config.issuer {
+        return Err(StringError("Id token issued by unknown issuer".to_string()));
+    }
+    let audience_matches = match claims.aud {
+        Value::String(ref aud) => *aud == config.client_id,
+        Value::Array(ref auds) => auds.iter()
+            .any(|aud| aud.as_str() == Some(config.client_id.as_ref())),
+        _ => false,
+    };
+    if !audience_matches {
+        return Err(StringError("Id token issued for another client".to_string()));
+    }
+    if claims.exp < Utc::now().timestamp() {
+        return Err(StringError("Id token is expired".to_string()));
+    }
+    Ok(claims)
+}
+
+fn fetch_json<T: DeserializeOwned>(
+    sent: Result<::reqwest::Response, ::reqwest::Error>,
+) -> OidcResult<T> {
+    let mut response = match sent {
+        Ok(response) => response,
+        Err(e) => return Err(StringError(format!("Cannot reach provider: {:?}", e))),
+    };
+    if !response.status().is_success() {
+        return Err(StringError(format!(
+            "Provider responded with {}",
+            response.status()
+        )));
+    }
+    match response.json::<T>() {
+        Ok(data) => Ok(data),
+        Err(e) => Err(StringError(format!("Bad provider response: {:?}", e))),
+    }
+}
+
+fn decode_payload<T: DeserializeOwned>(payload_b64: &str) -> OidcResult<T> {
+    match base64::decode_config(payload_b64, base64::URL_SAFE_NO_PAD) {
+        Ok(bytes) => match serde_json::from_slice::<T>(&bytes) {
+            Ok(payload) => Ok(payload),
+            Err(e) => Err(StringError(format!("Bad id token payload: {:?}", e))),
+        },
+        Err(e) => Err(StringError(format!("Bad id token encoding: {:?}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Serves discovery document and token endpoint of issuer on random local port,
+    // token endpoint answers with id token carrying claims made for the issuer url
+    fn mock_issuer<F: FnOnce(&str) -> String>(claims: F) -> OidcConfig {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let discovery = format!(
+            "{{\"issuer\":\"{0}\",\"authorization_endpoint\":\"{0}/authorize\",\
+             \"token_endpoint\":\"{0}/token\"}}",
+            issuer
+        );
+        let id_token = format!(
+            "{}.{}.",
+            base64::encode_config(r#"{"alg":"RS256"}"#, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(&claims(&issuer), base64::URL_SAFE_NO_PAD)
+        );
+        let tokens = format!(r#"{{"id_token":"{}"}}"#, id_token);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream);
+                let body = if request.starts_with("GET /.well-known/openid-configuration") {
+                    &discovery
+                } else {
+                    &tokens
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                ).unwrap();
+            }
+        });
+        OidcConfig {
+            issuer,
+            client_id: "pizza".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_uri: "http://localhost/callback".to_string(),
+            scopes: "openid email".to_string(),
+        }
+    }
+
+    // Whole request is read, so client gets response instead of reset connection
+    fn read_request<R: Read>(stream: &mut R) -> String {
+        let mut data: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            data.extend_from_slice(&buf[..n]);
+            let request = String::from_utf8_lossy(&data).to_string();
+            if let Some(end) = request.find("\r\n\r\n") {
+                let length = request[..end]
+                    .lines()
+                    .filter_map(|line| {
+                        let mut parts = line.splitn(2, ':');
+                        match (parts.next(), parts.next()) {
+                            (Some(name), Some(value))
+                                if name.eq_ignore_ascii_case("content-length") =>
+                            {
+                                value.trim().parse::<usize>().ok()
+                            }
+                            _ => None,
+                        }
+                    })
+                    .next()
+                    .unwrap_or(0);
+                if n == 0 || data.len() >= end + 4 + length {
+                    return request;
+                }
+            } else if n == 0 {
+                return request;
+            }
+        }
+    }
+
+    fn claims(issuer: &str, aud: &str, exp: i64) -> String {
+        format!(
+            "{{\"iss\":\"{}\",\"aud\":\"{}\",\"exp\":{},\"sub\":\"42\",\
+             \"nonce\":\"n-0S6_WzA2Mj\",\"email\":\"lempiy@example.com\",\
+             \"email_verified\":true}}",
+            issuer, aud, exp
+        )
+    }
+
+    #[test]
+    fn exchanges_code_with_mock_issuer() {
+        let exp = Utc::now().timestamp() + 60;
+        let config = mock_issuer(|issuer| claims(issuer, "pizza", exp));
+        let metadata = discover(&config).unwrap();
+        let claims = exchange_code(&config, &metadata, "code").unwrap();
+        assert_eq!(claims.sub, "42");
+        assert_eq!(claims.nonce, Some("n-0S6_WzA2Mj".to_string()));
+        assert_eq!(claims.email, Some("lempiy@example.com".to_string()));
+        assert!(claims.email_verified);
+    }
+
+    #[test]
+    fn rejects_token_of_another_client() {
+        let exp = Utc::now().timestamp() + 60;
+        let config = mock_issuer(|issuer| claims(issuer, "other", exp));
+        let metadata = discover(&config).unwrap();
+        assert!(exchange_code(&config, &metadata, "code").is_err());
+    }
+
+    #[test]
+    fn rejects_expired_token() {
+        let exp = Utc::now().timestamp() - 60;
+        let config = mock_issuer(|issuer| claims(issuer, "pizza", exp));
+        let metadata = discover(&config).unwrap();
+        assert!(exchange_code(&config, &metadata, "code").is_err());
+    }
+
+    #[test]
+    fn requires_https_out_of_loopback() {
+        assert!(check_secure("https://id.example.com").is_ok());
+        assert!(check_secure("http://127.0.0.1:8080").is_ok());
+        assert!(check_secure("http://localhost").is_ok());
+        assert!(check_secure("http://id.example.com").is_err());
+        assert!(check_secure("ftp://localhost").is_err());
+    }
+}
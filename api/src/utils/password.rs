@@ -0,0 +1,28 @@
+use bcrypt::{self, BcryptResult, DEFAULT_COST};
+
+// Prefixes of bcrypt modular crypt format, anything else
+// stored in password column is a legacy plain text value
+const BCRYPT_PREFIXES: [&str; 3] = ["$2a$", "$2b$", "$2y$"];
+
+pub fn hash_password(password: &str) -> BcryptResult<String> {
+    bcrypt::hash(password, DEFAULT_COST)
+}
+
+pub fn is_hashed(stored: &str) -> bool {
+    BCRYPT_PREFIXES.iter().any(|p| stored.starts_with(p))
+}
+
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    if is_hashed(stored) {
+        bcrypt::verify(password, stored).unwrap_or(false)
+    } else {
+        constant_time_eq(password.as_bytes(), stored.as_bytes())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
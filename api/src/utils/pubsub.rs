@@ -41,13 +41,13 @@ impl Manager {
                         let mut pubsub = sub_pub_sub.lock().unwrap();
                         pubsub
                             .subscribe::<&str>(&channel_name)
-                            .expect(&("Unable to subscribe to ".to_string() + &channel_name));
+                            .unwrap_or_else(|_| panic!("Unable to subscribe to {}", channel_name));
                     }
                     Action::Unsubscribe(channel_name) => {
                         let mut pubsub = sub_pub_sub.lock().unwrap();
                         pubsub
                             .unsubscribe::<&str>(&channel_name)
-                            .expect(&("Unable to unsubscribe to ".to_string() + &channel_name));
+                            .unwrap_or_else(|_| panic!("Unable to unsubscribe to {}", channel_name));
                     }
                 }
             }
@@ -1,9 +1,12 @@
 use rusoto_core::Region;
-use rusoto_s3::{PutObjectError, PutObjectOutput, PutObjectRequest, S3, S3Client};
+use rusoto_s3::{DeleteObjectError, DeleteObjectOutput, DeleteObjectRequest, PutObjectError,
+                PutObjectOutput, PutObjectRequest, S3, S3Client};
 use std::fs::File;
 use std::io::Read;
 use std::sync::MutexGuard;
 
+pub const PIZZA_IMAGES_BUCKET: &str = "pizza-kottans";
+
 pub fn configure_s3_client() -> S3Client {
     S3Client::simple(Region::EuCentral1)
 }
@@ -31,3 +34,16 @@ pub fn put_object_with_filename(
         }
     }
 }
+
+pub fn delete_object_with_filename(
+    client: &MutexGuard<S3Client>,
+    bucket: &str,
+    filename: &str,
+) -> Result<DeleteObjectOutput, DeleteObjectError> {
+    let req = DeleteObjectRequest {
+        bucket: bucket.to_owned(),
+        key: "upload/".to_string() + filename,
+        ..Default::default()
+    };
+    client.delete_object(&req).sync()
+}
@@ -0,0 +1,107 @@
+use redis::{Commands, Connection, RedisResult};
+use std::cmp;
+use std::sync::MutexGuard;
+use utils::config::{login_failures_window, login_lockout_base, login_lockout_max,
+                    login_max_attempts, login_max_attempts_per_ip};
+
+// Max power of two used for lockout backoff, keeps multiplication in bounds
+const MAX_BACKOFF_EXPONENT: i64 = 20;
+
+#[derive(Clone, Copy)]
+pub enum Scope {
+    // login failures, keyed by uuid of user or by login itself when it is unknown
+    Username,
+    Store,
+    Ip,
+    // password reset requests are counted apart from login failures,
+    // so resets do not lock out logins from the same address
+    ResetEmail,
+    ResetIp,
+    // password checks of logged in user, keyed by user uuid
+    Account,
+}
+
+impl Scope {
+    fn name(&self) -> &'static str {
+        match *self {
+            Scope::Username => "username",
+            Scope::Store => "store",
+            Scope::Ip => "ip",
+            Scope::ResetEmail => "reset-email",
+            Scope::ResetIp => "reset-ip",
+            Scope::Account => "account",
+        }
+    }
+
+    fn max_attempts(&self) -> i64 {
+        match *self {
+            Scope::Ip | Scope::ResetIp => login_max_attempts_per_ip(),
+            _ => login_max_attempts(),
+        }
+    }
+
+    // Usernames and emails are case-insensitive, so are their counters
+    fn normalize(&self, id: &str) -> String {
+        match *self {
+            Scope::Username | Scope::ResetEmail => id.trim().to_lowercase(),
+            _ => id.to_string(),
+        }
+    }
+}
+
+fn failures_key(scope: Scope, id: &str) -> String {
+    format!("login-failures-{}-{}", scope.name(), scope.normalize(id))
+}
+
+fn lockout_key(scope: Scope, id: &str) -> String {
+    format!("login-lockout-{}-{}", scope.name(), scope.normalize(id))
+}
+
+// Returns seconds left till the longest of given lockouts ends
+pub fn locked_for(
+    rds: &MutexGuard<Connection>,
+    targets: &[(Scope, &str)],
+) -> RedisResult<Option<i64>> {
+    let mut left: Option<i64> = None;
+    for &(scope, id) in targets.iter() {
+        let ttl: i64 = match redis::cmd("TTL").arg(lockout_key(scope, id)).query(&**rds) {
+            Ok(ttl) => ttl,
+            Err(e) => return Err(e),
+        };
+        if ttl > 0 && left.is_none_or(|l| ttl > l) {
+            left = Some(ttl);
+        };
+    }
+    Ok(left)
+}
+
+pub fn register_failure(rds: &MutexGuard<Connection>, scope: Scope, id: &str) -> RedisResult<()> {
+    let key = failures_key(scope, id);
+    let failures: i64 = match rds.incr(key.clone(), 1) {
+        Ok(failures) => failures,
+        Err(e) => return Err(e),
+    };
+    if let Err(e) = rds.expire::<String, i32>(key.clone(), login_failures_window() as usize) {
+        return Err(e);
+    };
+    let max_attempts = scope.max_attempts();
+    if failures < max_attempts {
+        return Ok(());
+    };
+    let exponent = cmp::min(failures - max_attempts, MAX_BACKOFF_EXPONENT);
+    let lockout = cmp::min(login_lockout_base() << exponent, login_lockout_max());
+    if let Err(e) =
+        rds.set_ex::<String, i64, ()>(lockout_key(scope, id), failures, lockout as usize)
+    {
+        return Err(e);
+    };
+    // failures counter should outlive lockout to keep backoff growing
+    rds.expire::<String, ()>(key, (login_failures_window() + lockout) as usize)
+}
+
+pub fn clear_failures(rds: &MutexGuard<Connection>, scope: Scope, id: &str) -> RedisResult<()> {
+    if let Err(e) = rds.del::<String, i32>(failures_key(scope, id)) {
+        return Err(e);
+    };
+    rds.del::<String, ()>(lockout_key(scope, id))
+}
@@ -0,0 +1,194 @@
+use rand::{OsRng, Rng};
+use ring::{constant_time, digest, hmac};
+use utils::config::env_or;
+use utils::random_token;
+
+// RFC 6238 defaults understood by every authenticator app
+const TOTP_PERIOD: i64 = 30;
+const TOTP_DIGITS: usize = 6;
+const TOTP_SECRET_LENGTH: usize = 20;
+// Codes of neighbour periods are accepted to tolerate clock drift
+const TOTP_ALLOWED_DRIFT: i64 = 1;
+
+pub const RECOVERY_CODES_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// Base32 encoded random secret
+pub fn generate_secret() -> String {
+    let mut rand_gen = OsRng::new().unwrap();
+    let mut secret = [0u8; TOTP_SECRET_LENGTH];
+    rand_gen.fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+pub fn otpauth_uri(account: &str, secret: &str) -> String {
+    let issuer = env_or("TOTP_ISSUER", "Pizza API".to_string());
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer.as_ref()),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer.as_ref()),
+        TOTP_DIGITS,
+        TOTP_PERIOD
+    )
+}
+
+// Returns time step of matched code, same step must not be accepted twice
+pub fn verify_code(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let key = match base32_decode(secret) {
+        Some(key) => key,
+        None => return None,
+    };
+    if code.len() != TOTP_DIGITS {
+        return None;
+    }
+    let current = now / TOTP_PERIOD;
+    for step in (current - TOTP_ALLOWED_DRIFT)..(current + TOTP_ALLOWED_DRIFT + 1) {
+        let expected = format!("{:06}", hotp(&key, step as u64));
+        if constant_time::verify_slices_are_equal(expected.as_bytes(), code.as_bytes()).is_ok() {
+            return Some(step);
+        }
+    }
+    None
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES_COUNT)
+        .map(|_| random_token()[..RECOVERY_CODE_LENGTH].to_lowercase())
+        .collect()
+}
+
+// Recovery codes are random enough to be stored as plain digest
+pub fn hash_recovery_code(code: &str) -> String {
+    digest::digest(&digest::SHA256, code.trim().to_lowercase().as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let signing_key = hmac::SigningKey::new(&digest::SHA1, key);
+    let mut message = [0u8; 8];
+    for i in 0..8 {
+        message[7 - i] = (counter >> (8 * i)) as u8;
+    }
+    let signature = hmac::sign(&signing_key, &message);
+    let hash = signature.as_ref();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24) | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8) | (hash[offset + 3] as u32);
+    binary % 10u32.pow(TOTP_DIGITS as u32)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data.iter() {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = match BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())
+        {
+            Some(value) => value as u32,
+            None => return None,
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(decoded)
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shared secret "12345678901234567890" of RFC 6238 Appendix B in base32
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    // SHA1 test vectors of RFC 6238 Appendix B, codes are cut to last 6 digits
+    const RFC_VECTORS: [(i64, &str); 6] = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+
+    #[test]
+    fn accepts_rfc_codes() {
+        for &(time, code) in RFC_VECTORS.iter() {
+            assert_eq!(verify_code(RFC_SECRET, code, time), Some(time / TOTP_PERIOD));
+        }
+    }
+
+    #[test]
+    fn accepts_codes_of_neighbour_periods() {
+        let (time, code) = RFC_VECTORS[3];
+        assert_eq!(
+            verify_code(RFC_SECRET, code, time + TOTP_PERIOD),
+            Some(time / TOTP_PERIOD)
+        );
+        assert_eq!(
+            verify_code(RFC_SECRET, code, time - TOTP_PERIOD),
+            Some(time / TOTP_PERIOD)
+        );
+    }
+
+    #[test]
+    fn rejects_expired_and_malformed_codes() {
+        let (time, code) = RFC_VECTORS[3];
+        assert_eq!(verify_code(RFC_SECRET, code, time + 3 * TOTP_PERIOD), None);
+        assert_eq!(verify_code(RFC_SECRET, "89005924", time), None);
+        assert_eq!(verify_code(RFC_SECRET, "5924", time), None);
+        assert_eq!(verify_code("not base32!", code, time), None);
+    }
+
+    #[test]
+    fn encodes_secret_to_base32() {
+        assert_eq!(base32_encode(b"12345678901234567890"), RFC_SECRET);
+        assert_eq!(
+            base32_decode(RFC_SECRET),
+            Some(b"12345678901234567890".to_vec())
+        );
+    }
+}
@@ -12,6 +12,6 @@ impl fmt::Display for StringError {
 
 impl Error for StringError {
     fn description(&self) -> &str {
-        &*self.0
+        &self.0
     }
 }
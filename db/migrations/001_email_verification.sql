@@ -0,0 +1,10 @@
+BEGIN;
+
+ALTER TABLE person ADD COLUMN verified_at TIMESTAMP WITH TIME ZONE;
+-- users created before verification was introduced are trusted
+UPDATE person SET verified_at = created_at WHERE verified_at IS NULL;
+
+-- what users with not verified email may do: 'full', 'read_only' or 'denied'
+ALTER TABLE store ADD COLUMN unverified_access varchar(20) NOT NULL DEFAULT 'full';
+
+COMMIT;
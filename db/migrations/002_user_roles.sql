@@ -0,0 +1,12 @@
+BEGIN;
+
+-- 'admin', 'cook' or 'member'
+ALTER TABLE person ADD COLUMN role varchar(20) NOT NULL DEFAULT 'member';
+
+-- earliest user of every store becomes its admin
+UPDATE person SET role = 'admin'
+WHERE uuid IN (
+    SELECT DISTINCT ON (store_id) uuid FROM person ORDER BY store_id, created_at
+);
+
+COMMIT;
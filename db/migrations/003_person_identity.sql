@@ -0,0 +1,14 @@
+BEGIN;
+
+-- subjects of external OpenID Connect providers bound to users
+CREATE TABLE person_identity (
+  issuer varchar(1000) not null,
+  subject varchar(255) not null,
+  person_uuid UUID not null,
+  store_id int references store(id) ON DELETE CASCADE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  PRIMARY KEY (issuer, subject)
+);
+CREATE INDEX person_identity_person_uuid_idx ON person_identity (person_uuid);
+
+COMMIT;
@@ -0,0 +1,9 @@
+BEGIN;
+
+-- secret is set upon enrollment, 2FA is active once totp_enabled_at is set
+ALTER TABLE person ADD COLUMN totp_secret varchar(64);
+ALTER TABLE person ADD COLUMN totp_enabled_at TIMESTAMP WITH TIME ZONE;
+-- sha256 digests of unused recovery codes
+ALTER TABLE person ADD COLUMN recovery_codes varchar(64)[] NOT NULL DEFAULT '{}';
+
+COMMIT;
@@ -0,0 +1,18 @@
+BEGIN;
+
+-- invite codes created by store admins, used instead of store password
+CREATE TABLE store_invite (
+  uuid UUID primary key,
+  store_id int references store(id) ON DELETE CASCADE,
+  code varchar(64) unique not null,
+  role varchar(20),
+  max_uses int not null,
+  uses int not null DEFAULT 0,
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_by UUID,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  revoked_at TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX store_invite_store_id_idx ON store_invite (store_id);
+
+COMMIT;
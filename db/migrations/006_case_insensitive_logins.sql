@@ -0,0 +1,21 @@
+-- Usernames and emails are compared case-insensitively since this version.
+-- Nothing is changed automatically: accounts listed below collide and should
+-- be renamed by hand, until then the password decides which of them logs in
+-- and none of them can take the colliding name or email on profile update.
+
+-- usernames differing only in letter case
+SELECT lower(username) AS username_collision,
+       array_agg(username || ' (' || uuid || ', store ' || store_id || ')'
+                 ORDER BY created_at) AS accounts
+FROM person
+GROUP BY lower(username)
+HAVING count(*) > 1;
+
+-- emails used by more than one account
+SELECT lower(email) AS email_collision,
+       array_agg(username || ' (' || uuid || ', store ' || store_id || ')'
+                 ORDER BY created_at) AS accounts
+FROM person
+WHERE email != ''
+GROUP BY lower(email)
+HAVING count(*) > 1;
@@ -0,0 +1,36 @@
+BEGIN;
+
+-- deleted pizzas are not waiting anymore, so they leave non accepted counter
+CREATE OR REPLACE FUNCTION count_non_accepted_rows()
+    RETURNS TRIGGER AS $count_rows$
+       declare was_counted boolean := false;
+       declare is_counted boolean := false;
+       BEGIN
+          -- only pizzas still waiting are counted: neither accepted nor deleted
+          IF (TG_OP = 'INSERT' OR TG_OP = 'UPDATE') THEN
+             is_counted := new.accepted = 0 AND new.deleted = 0;
+          END IF;
+          IF (TG_OP = 'UPDATE' OR TG_OP = 'DELETE') THEN
+             was_counted := old.accepted = 0 AND old.deleted = 0;
+          END IF;
+          IF (is_counted AND NOT was_counted) THEN
+             UPDATE rowcount
+                SET total_rows = total_rows + 1
+                WHERE table_name = TG_RELNAME || '_non_accepted';
+          ELSIF (was_counted AND NOT is_counted) THEN
+             UPDATE rowcount
+                SET total_rows = total_rows - 1
+                WHERE table_name = TG_RELNAME || '_non_accepted';
+          END IF;
+          RETURN NULL;
+       END;
+    $count_rows$ LANGUAGE plpgsql;
+
+UPDATE rowcount SET total_rows = (
+    SELECT count(*) FROM pizza
+    WHERE accepted = 0 AND deleted = 0
+      AND 'pizza_' || store_id || '_non_accepted' = rowcount.table_name
+)
+WHERE table_name LIKE 'pizza\_%\_non\_accepted';
+
+COMMIT;
@@ -0,0 +1,23 @@
+BEGIN;
+
+-- 'created', 'preparing', 'baking', 'ready', 'picked_up' or 'cancelled',
+-- accepted pizza is ready and deleted one is cancelled
+ALTER TABLE pizza ADD COLUMN status varchar(20) NOT NULL DEFAULT 'created' CHECK (status IN (
+    'created', 'preparing', 'baking', 'ready', 'picked_up', 'cancelled'
+));
+ALTER TABLE pizza ADD COLUMN preparing_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE pizza ADD COLUMN baking_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE pizza ADD COLUMN ready_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE pizza ADD COLUMN picked_up_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE pizza ADD COLUMN cancelled_at TIMESTAMP WITH TIME ZONE;
+
+-- time of acceptance was not stored, time_prepared is the closest one,
+-- time of deletion is unknown at all
+UPDATE pizza SET ready_at = time_prepared WHERE accepted = 1;
+UPDATE pizza SET status = CASE
+    WHEN deleted = 1 THEN 'cancelled'
+    WHEN accepted = 1 THEN 'ready'
+    ELSE 'created'
+END;
+
+COMMIT;
@@ -0,0 +1,16 @@
+BEGIN;
+
+-- 'auto' stores have pizzas accepted once time_prepared comes,
+-- in 'manual' ones kitchen staff accepts or rejects them
+ALTER TABLE store ADD COLUMN accept_mode varchar(10) NOT NULL DEFAULT 'auto'
+    CHECK (accept_mode IN ('auto', 'manual'));
+
+-- rejected pizza is deleted like cancelled one but cannot be restored
+ALTER TABLE pizza DROP CONSTRAINT pizza_status_check;
+ALTER TABLE pizza ADD CONSTRAINT pizza_status_check CHECK (status IN (
+    'created', 'preparing', 'baking', 'ready', 'picked_up', 'cancelled', 'rejected'
+));
+ALTER TABLE pizza ADD COLUMN rejected_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE pizza ADD COLUMN reject_reason varchar(1000);
+
+COMMIT;
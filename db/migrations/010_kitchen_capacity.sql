@@ -0,0 +1,8 @@
+BEGIN;
+
+-- number of pizzas baked at once and pizzas per hour staff prepares for baking,
+-- preparation time of new pizza depends on them and on pizzas waiting before
+ALTER TABLE store ADD COLUMN ovens integer NOT NULL DEFAULT 2 CHECK (ovens > 0);
+ALTER TABLE store ADD COLUMN throughput integer NOT NULL DEFAULT 30 CHECK (throughput > 0);
+
+COMMIT;
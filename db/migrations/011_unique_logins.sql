@@ -0,0 +1,29 @@
+-- Usernames and emails become unique regardless of letter case, so concurrent
+-- sign ups cannot both take the same one. Accounts listed by
+-- 006_case_insensitive_logins.sql should be renamed first, otherwise index
+-- creation fails. Users live in per-store partitions, which do not share
+-- indexes of parent table, so each partition is indexed by itself.
+BEGIN;
+
+CREATE UNIQUE INDEX person_lower_username_idx ON person (lower(username));
+CREATE UNIQUE INDEX person_lower_email_idx ON person (lower(email)) WHERE email != '';
+
+DO $$
+DECLARE
+    part_id text;
+BEGIN
+    FOR part_id IN SELECT id::text FROM store LOOP
+        EXECUTE format(
+            $sql$
+            CREATE UNIQUE INDEX %2$I ON %1$I (lower(username));
+            CREATE UNIQUE INDEX %3$I ON %1$I (lower(email)) WHERE email != '';
+            $sql$,
+            'person_' || part_id,
+            'person_lower_username_idx_' || part_id,
+            'person_lower_email_idx_' || part_id
+        );
+    END LOOP;
+END;
+$$;
+
+COMMIT;
@@ -7,7 +7,11 @@ CREATE TABLE store(
     name VARCHAR(100) UNIQUE NOT NULL,
     lat REAL NOT NULL,
     lon REAL NOT NULL,
-    password varchar(100) not null
+    password varchar(100) not null,
+    unverified_access varchar(20) NOT NULL DEFAULT 'full',
+    accept_mode varchar(10) NOT NULL DEFAULT 'auto' CHECK (accept_mode IN ('auto', 'manual')),
+    ovens integer NOT NULL DEFAULT 2 CHECK (ovens > 0),
+    throughput integer NOT NULL DEFAULT 30 CHECK (throughput > 0)
 );
 
 --person
@@ -20,10 +24,45 @@ CREATE TABLE person (
   email varchar(100) not null,
   password varchar(100) not null,
   created_at TIMESTAMP WITH TIME ZONE NOT NULL,
-  last_login TIMESTAMP WITH TIME ZONE
+  last_login TIMESTAMP WITH TIME ZONE,
+  verified_at TIMESTAMP WITH TIME ZONE,
+  role varchar(20) NOT NULL DEFAULT 'member',
+  totp_secret varchar(64),
+  totp_enabled_at TIMESTAMP WITH TIME ZONE,
+  recovery_codes varchar(64)[] NOT NULL DEFAULT '{}'
 );
 CREATE UNIQUE INDEX uuid_idx ON person (uuid);
 CREATE UNIQUE INDEX username_password_idx ON person (username, password);
+CREATE UNIQUE INDEX person_lower_username_idx ON person (lower(username));
+CREATE UNIQUE INDEX person_lower_email_idx ON person (lower(email)) WHERE email != '';
+
+--store_invite
+DROP TABLE IF EXISTS store_invite cascade;
+CREATE TABLE store_invite (
+  uuid UUID primary key,
+  store_id int references store(id) ON DELETE CASCADE,
+  code varchar(64) unique not null,
+  role varchar(20),
+  max_uses int not null,
+  uses int not null DEFAULT 0,
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_by UUID,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  revoked_at TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX store_invite_store_id_idx ON store_invite (store_id);
+
+--person_identity
+DROP TABLE IF EXISTS person_identity cascade;
+CREATE TABLE person_identity (
+  issuer varchar(1000) not null,
+  subject varchar(255) not null,
+  person_uuid UUID not null,
+  store_id int references store(id) ON DELETE CASCADE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  PRIMARY KEY (issuer, subject)
+);
+CREATE INDEX person_identity_person_uuid_idx ON person_identity (person_uuid);
 
 --tag
 DROP TABLE IF EXISTS tag cascade;
@@ -58,7 +97,17 @@ CREATE TABLE pizza (
     description text,
     img_url varchar(1000) not null,
     created_date TIMESTAMP WITH TIME ZONE NOT NULL,
-    time_prepared TIMESTAMP WITH TIME ZONE NOT NULL
+    time_prepared TIMESTAMP WITH TIME ZONE NOT NULL,
+    status varchar(20) NOT NULL DEFAULT 'created' CHECK (status IN (
+        'created', 'preparing', 'baking', 'ready', 'picked_up', 'cancelled', 'rejected'
+    )),
+    preparing_at TIMESTAMP WITH TIME ZONE,
+    baking_at TIMESTAMP WITH TIME ZONE,
+    ready_at TIMESTAMP WITH TIME ZONE,
+    picked_up_at TIMESTAMP WITH TIME ZONE,
+    cancelled_at TIMESTAMP WITH TIME ZONE,
+    rejected_at TIMESTAMP WITH TIME ZONE,
+    reject_reason varchar(1000)
 );
 
 CREATE INDEX pizza_user_uuid_idx ON pizza (user_uuid);
@@ -105,6 +154,8 @@ CREATE OR REPLACE FUNCTION init_new_store()
                     DO INSTEAD INSERT INTO %1$I VALUES (NEW.*);
                 CREATE UNIQUE INDEX %4$I ON %1$I (uuid);
                 CREATE UNIQUE INDEX %5$I ON %1$I (username, password);
+                CREATE UNIQUE INDEX %6$I ON %1$I (lower(username));
+                CREATE UNIQUE INDEX %7$I ON %1$I (lower(email)) WHERE email != '';
                 ALTER TABLE %1$I ADD PRIMARY KEY(uuid);
                 INSERT INTO rowcount (table_name, total_rows)
                     VALUES  (%1$L,  0);
@@ -116,7 +167,9 @@ CREATE OR REPLACE FUNCTION init_new_store()
                  part_id,
                 'person_insert_rule_' || part_id,
                 'uuid_idx_' || part_id,
-                'username_password_idx_' || part_id
+                'username_password_idx_' || part_id,
+                'person_lower_username_idx_' || part_id,
+                'person_lower_email_idx_' || part_id
             );
             --------------------------------------------
 
@@ -247,20 +300,21 @@ CREATE OR REPLACE FUNCTION init_new_store()
 
 CREATE OR REPLACE FUNCTION count_non_accepted_rows()
     RETURNS TRIGGER AS $count_rows$
+       declare was_counted boolean := false;
+       declare is_counted boolean := false;
        BEGIN
-          IF (TG_OP = 'INSERT' AND new.accepted = 0) THEN
+          -- only pizzas still waiting are counted: neither accepted nor deleted
+          IF (TG_OP = 'INSERT' OR TG_OP = 'UPDATE') THEN
+             is_counted := new.accepted = 0 AND new.deleted = 0;
+          END IF;
+          IF (TG_OP = 'UPDATE' OR TG_OP = 'DELETE') THEN
+             was_counted := old.accepted = 0 AND old.deleted = 0;
+          END IF;
+          IF (is_counted AND NOT was_counted) THEN
              UPDATE rowcount
                 SET total_rows = total_rows + 1
                 WHERE table_name = TG_RELNAME || '_non_accepted';
-          ELSIF (TG_OP = 'UPDATE' AND new.accepted != old.accepted AND new.accepted = 0) THEN
-             UPDATE rowcount
-                 SET total_rows = total_rows + 1
-                 WHERE table_name = TG_RELNAME || '_non_accepted';
-          ELSIF (TG_OP = 'UPDATE' AND new.accepted != old.accepted AND new.accepted = 1) THEN
-             UPDATE rowcount
-                 SET total_rows = total_rows - 1
-                 WHERE table_name = TG_RELNAME || '_non_accepted';
-          ELSIF (TG_OP = 'DELETE' AND old.accepted = 0) THEN
+          ELSIF (was_counted AND NOT is_counted) THEN
              UPDATE rowcount
                 SET total_rows = total_rows - 1
                 WHERE table_name = TG_RELNAME || '_non_accepted';